@@ -0,0 +1,166 @@
+/*
+    the account checks every handler need , in one place so each instruction declare its accounts the same way :
+
+        let mut accounts = Accounts::new(program_id, accounts);
+        let buyer = accounts.next_account()?.signer()?.writable()?.info();
+        let deal_account = accounts.next_account()?.writable()?.holds::<DealData>()?.info();
+        let system_program = accounts.next_account()?.system_program()?.info();
+
+    next_account() take the next account and refuse a key that was already taken in this instruction , so one
+    account can never stand for two roles (the same vault as payee and rent receiver , the same listing twice ...).
+    the few roles that may be the same wallet on purpose (a crank that is also the buyer) use next_shared().
+    each check log what went wrong and return the matching MarketplaceError , the handler only keep the checks
+    that need the data (this deal belong to that listing , this signer is the seller ...).
+*/
+
+use solana_program::{account_info::AccountInfo, msg, program_error::ProgramError, pubkey::Pubkey};
+
+use crate::error::MarketplaceError;
+use crate::state::{ProgramAccount, StoredBump, load};
+
+pub struct Accounts<'a, 'b> {
+    program_id: &'b Pubkey,
+    iter: std::slice::Iter<'b, AccountInfo<'a>>,
+    taken: Vec<&'b Pubkey>,
+}
+
+impl<'a, 'b> Accounts<'a, 'b> {
+    pub fn new(program_id: &'b Pubkey, accounts: &'b [AccountInfo<'a>]) -> Self {
+        Self {
+            program_id,
+            iter: accounts.iter(),
+            taken: Vec::with_capacity(accounts.len()),
+        }
+    }
+
+    // the next account , it must not repeat an account taken before
+    pub fn next_account(&mut self) -> Result<Account<'a, 'b>, ProgramError> {
+        let account = self.next_shared()?;
+        if self.taken[..self.taken.len() - 1].contains(&account.info.key) {
+            msg!("Error: Account {} is passed twice", account.info.key);
+            return Err(MarketplaceError::DuplicateAccount.into());
+        }
+        Ok(account)
+    }
+
+    // the next account if the client passed one more , for the optional accounts at the end of a list
+    pub fn next_optional(&mut self) -> Result<Option<Account<'a, 'b>>, ProgramError> {
+        if self.iter.len() == 0 {
+            return Ok(None);
+        }
+        self.next_account().map(Some)
+    }
+
+    // same , but it may be one taken before
+    pub fn next_optional_shared(&mut self) -> Result<Option<Account<'a, 'b>>, ProgramError> {
+        if self.iter.len() == 0 {
+            return Ok(None);
+        }
+        self.next_shared().map(Some)
+    }
+
+    // the next account , it may be one taken before
+    pub fn next_shared(&mut self) -> Result<Account<'a, 'b>, ProgramError> {
+        let info = self.iter.next().ok_or(ProgramError::NotEnoughAccountKeys)?;
+        self.taken.push(info.key);
+        Ok(Account {
+            info,
+            program_id: self.program_id,
+        })
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct Account<'a, 'b> {
+    info: &'b AccountInfo<'a>,
+    program_id: &'b Pubkey,
+}
+
+impl<'a, 'b> Account<'a, 'b> {
+    pub fn info(self) -> &'b AccountInfo<'a> {
+        self.info
+    }
+
+    pub fn signer(self) -> Result<Self, ProgramError> {
+        if !self.info.is_signer {
+            msg!("Error: Account {} should be a signer", self.info.key);
+            return Err(MarketplaceError::MissingSigner.into());
+        }
+        Ok(self)
+    }
+
+    pub fn writable(self) -> Result<Self, ProgramError> {
+        if !self.info.is_writable {
+            msg!("Error: Account {} should be writable", self.info.key);
+            return Err(MarketplaceError::AccountNotWritable.into());
+        }
+        Ok(self)
+    }
+
+    pub fn owned(self) -> Result<Self, ProgramError> {
+        if self.info.owner != self.program_id {
+            msg!("Error: Account {} not owned by this program", self.info.key);
+            return Err(MarketplaceError::WrongAccountOwner.into());
+        }
+        Ok(self)
+    }
+
+    // owned by this program and holding a T : right size , discriminator and version
+    pub fn holds<T: ProgramAccount>(self) -> Result<Self, ProgramError> {
+        self.owned()?;
+        if let Err(e) = load::<T>(&self.info.try_borrow_data()?) {
+            msg!("Error: Account {} does not hold the expected data", self.info.key);
+            return Err(e.into());
+        }
+        Ok(self)
+    }
+
+    // holds a T that sit at seeds with the bump it stored when it was created , the usual check for a PDA
+    // this program made before. return the bump for signing
+    pub fn stored_pda<T: StoredBump>(
+        self,
+        seeds: &[&[u8]],
+        error: MarketplaceError,
+    ) -> Result<(Self, u8), ProgramError> {
+        self.holds::<T>()?;
+        let bump = load::<T>(&self.info.try_borrow_data()?)?.bump();
+        Ok((self.pda(seeds, bump, error)?, bump))
+    }
+
+    // the PDA of seeds with a bump we already know , one hash instead of a search
+    pub fn pda(self, seeds: &[&[u8]], bump: u8, error: MarketplaceError) -> Result<Self, ProgramError> {
+        let mut with_bump = seeds.to_vec();
+        let bump = [bump];
+        with_bump.push(&bump);
+        match Pubkey::create_program_address(&with_bump, self.program_id) {
+            Ok(pda) if pda == *self.info.key => Ok(self),
+            _ => {
+                msg!("Error: Account {} does not match its seeds", self.info.key);
+                Err(error.into())
+            }
+        }
+    }
+
+    // the canonical PDA of seeds , only for accounts created here : the bump found is the one they store
+    pub fn find_pda(self, seeds: &[&[u8]], error: MarketplaceError) -> Result<(Self, u8), ProgramError> {
+        let (pda, bump) = Pubkey::find_program_address(seeds, self.program_id);
+        if pda != *self.info.key {
+            msg!("Error: Account {} does not match its seeds", self.info.key);
+            return Err(error.into());
+        }
+        Ok((self, bump))
+    }
+
+    // a fixed key , like a program id or a key stored in another account
+    pub fn is_key(self, key: &Pubkey, error: MarketplaceError) -> Result<Self, ProgramError> {
+        if self.info.key != key {
+            msg!("Error: Account {} should be {}", self.info.key, key);
+            return Err(error.into());
+        }
+        Ok(self)
+    }
+
+    pub fn system_program(self) -> Result<Self, ProgramError> {
+        self.is_key(&solana_system_interface::program::ID, MarketplaceError::WrongSystemProgram)
+    }
+}
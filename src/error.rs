@@ -0,0 +1,297 @@
+/*
+    every error the program can return.
+    each variant is sent to the client as ProgramError::Custom(code) , the code is the number written next to the variant.
+    the codes are stable , the frontend show messages from them so never reorder or reuse a number ,
+    new errors are always added at the end with the next free number.
+
+    client side decoding :
+        ProgramError::Custom(code) -> MarketplaceError::try_from(code) -> to_str() / Display
+    or directly program_error.to_str::<MarketplaceError>()
+*/
+
+use solana_program::program_error::ProgramError;
+use solana_program_error::ToStr;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u32)]
+pub enum MarketplaceError {
+    // instruction decoding
+    EmptyInstruction = 0,
+    UnknownInstruction = 1,
+    PayloadTooShort = 2,
+    PayloadTooLong = 3,
+    ChunkLengthMismatch = 4,
+    UnknownHoldInstruction = 5, // no longer returned , HOLD_ACCOUNT is retired
+    UnsupportedVersion = 6,
+
+    // accounts
+    MissingSigner = 7,
+    WrongAccountOwner = 8,
+    WrongListingPda = 9,
+    WrongBuyPda = 10, // the deal account , kept its old name so the code stay the same
+    EscrowMismatch = 11, // no longer returned , the escrow live in the deal account
+    WrongTempPda = 12,   // no longer returned , the TEMP account is retired
+    WrongRegistryPda = 13,
+    InvalidAccountLayout = 14,
+
+    // listing and deal rules
+    NotBuyer = 15,
+    ListingNotActive = 16,
+    ListingContentLocked = 17,
+    ListingAlreadyPublished = 18,
+    ChunkTooLarge = 19,
+    ChunkOutOfBounds = 20,
+    ArithmeticOverflow = 21,
+
+    // account headers
+    WrongAccountDiscriminator = 22,
+    UnsupportedAccountVersion = 23,
+    AccountAlreadyInitialized = 24,
+
+    // listing lifecycle
+    ListingReserved = 25,
+    IllegalListingTransition = 26,
+
+    // escrow accounting
+    EscrowUnderfunded = 27,
+    EscrowPayeeMismatch = 28,
+    LamportImbalance = 29,
+
+    // deal binding
+    ItemIdMismatch = 30,
+    NotSeller = 31,
+
+    // price lock
+    PriceAboveMax = 32,
+    ListingRevisionMismatch = 33,
+
+    // delivery
+    DealNotShipped = 34,
+    DealAlreadyShipped = 35,
+    ConfirmWindowOpen = 36,
+
+    // deal timeouts
+    AcceptDeadlinePassed = 37,
+    AcceptWindowOpen = 38,
+
+    // disputes
+    NoArbiter = 39,
+    NotArbiter = 40,
+    NotDealParty = 41,
+    DealDisputed = 42,
+    DealNotDisputed = 43,
+    InvalidSplit = 44,
+    WrongDisputePda = 45,
+
+    // token payments
+    WrongTokenProgram = 46,
+    InvalidTokenAccount = 47,
+    UnsupportedMint = 48,
+    WrongMint = 49,
+    WrongTokenVault = 50,
+    TokenAccountMismatch = 51,
+
+    // marketplace config and treasury
+    NotAdmin = 52,
+    WrongConfigPda = 53,
+    WrongTreasuryPda = 54,
+    InvalidFeeBps = 55,
+    TreasuryInsufficient = 56,
+    WrongProgramData = 57,
+
+    // marketplace policy
+    ArbiterNotApproved = 58,
+    PriceAboveLimit = 59,
+    InvalidWindow = 60,
+    NotPendingAdmin = 61,
+    MarketplacePaused = 62,
+
+    // account closing
+    AccountClosed = 63, // no longer returned , a closed account is zero length and system owned
+
+    // account constraints
+    AccountNotWritable = 64,
+    DuplicateAccount = 65,
+    WrongSystemProgram = 66,
+
+    // registry rent
+    InvalidRentPolicy = 67,
+    RentAboveProceeds = 68,
+    RentPayerNotSigner = 69,
+    RentPolicyNotForTokens = 70,
+}
+
+impl From<MarketplaceError> for ProgramError {
+    fn from(e: MarketplaceError) -> Self {
+        ProgramError::Custom(e as u32)
+    }
+}
+
+impl TryFrom<u32> for MarketplaceError {
+    type Error = ProgramError;
+
+    fn try_from(code: u32) -> Result<Self, Self::Error> {
+        use MarketplaceError::*;
+        let e = match code {
+            0 => EmptyInstruction,
+            1 => UnknownInstruction,
+            2 => PayloadTooShort,
+            3 => PayloadTooLong,
+            4 => ChunkLengthMismatch,
+            5 => UnknownHoldInstruction,
+            6 => UnsupportedVersion,
+            7 => MissingSigner,
+            8 => WrongAccountOwner,
+            9 => WrongListingPda,
+            10 => WrongBuyPda,
+            11 => EscrowMismatch,
+            12 => WrongTempPda,
+            13 => WrongRegistryPda,
+            14 => InvalidAccountLayout,
+            15 => NotBuyer,
+            16 => ListingNotActive,
+            17 => ListingContentLocked,
+            18 => ListingAlreadyPublished,
+            19 => ChunkTooLarge,
+            20 => ChunkOutOfBounds,
+            21 => ArithmeticOverflow,
+            22 => WrongAccountDiscriminator,
+            23 => UnsupportedAccountVersion,
+            24 => AccountAlreadyInitialized,
+            25 => ListingReserved,
+            26 => IllegalListingTransition,
+            27 => EscrowUnderfunded,
+            28 => EscrowPayeeMismatch,
+            29 => LamportImbalance,
+            30 => ItemIdMismatch,
+            31 => NotSeller,
+            32 => PriceAboveMax,
+            33 => ListingRevisionMismatch,
+            34 => DealNotShipped,
+            35 => DealAlreadyShipped,
+            36 => ConfirmWindowOpen,
+            37 => AcceptDeadlinePassed,
+            38 => AcceptWindowOpen,
+            39 => NoArbiter,
+            40 => NotArbiter,
+            41 => NotDealParty,
+            42 => DealDisputed,
+            43 => DealNotDisputed,
+            44 => InvalidSplit,
+            45 => WrongDisputePda,
+            46 => WrongTokenProgram,
+            47 => InvalidTokenAccount,
+            48 => UnsupportedMint,
+            49 => WrongMint,
+            50 => WrongTokenVault,
+            51 => TokenAccountMismatch,
+            52 => NotAdmin,
+            53 => WrongConfigPda,
+            54 => WrongTreasuryPda,
+            55 => InvalidFeeBps,
+            56 => TreasuryInsufficient,
+            57 => WrongProgramData,
+            58 => ArbiterNotApproved,
+            59 => PriceAboveLimit,
+            60 => InvalidWindow,
+            61 => NotPendingAdmin,
+            62 => MarketplacePaused,
+            63 => AccountClosed,
+            64 => AccountNotWritable,
+            65 => DuplicateAccount,
+            66 => WrongSystemProgram,
+            67 => InvalidRentPolicy,
+            68 => RentAboveProceeds,
+            69 => RentPayerNotSigner,
+            70 => RentPolicyNotForTokens,
+            _ => return Err(ProgramError::InvalidArgument),
+        };
+        Ok(e)
+    }
+}
+
+impl ToStr for MarketplaceError {
+    fn to_str(&self) -> &'static str {
+        match self {
+            Self::EmptyInstruction => "Error: Instruction data is empty",
+            Self::UnknownInstruction => "Error: Unknown instruction tag",
+            Self::PayloadTooShort => "Error: Instruction payload is shorter than expected",
+            Self::PayloadTooLong => "Error: Instruction payload is longer than expected",
+            Self::ChunkLengthMismatch => "Error: Description chunk length does not match its bytes",
+            Self::UnknownHoldInstruction => "Error: Unknown HOLD_ACCOUNT sub-instruction",
+            Self::UnsupportedVersion => "Error: Unsupported instruction format version",
+            Self::MissingSigner => "Error: A required account did not sign",
+            Self::WrongAccountOwner => "Error: Account is not owned by this program",
+            Self::WrongListingPda => "Error: Listing account does not match its seeds",
+            Self::WrongBuyPda => "Error: Deal account does not match its seeds",
+            Self::EscrowMismatch => "Error: Escrow account does not match this deal",
+            Self::WrongTempPda => "Error: Temp account does not match its seeds",
+            Self::WrongRegistryPda => "Error: Registry account does not match its seeds",
+            Self::InvalidAccountLayout => "Error: Account data has the wrong size or layout",
+            Self::NotBuyer => "Error: Signer is not the buyer of this deal",
+            Self::ListingNotActive => "Error: Listing is not open for buying",
+            Self::ListingContentLocked => "Error: Listing content is locked",
+            Self::ListingAlreadyPublished => "Error: Listing is already published",
+            Self::ChunkTooLarge => "Error: Description chunk is too large",
+            Self::ChunkOutOfBounds => "Error: Description chunk is out of bounds",
+            Self::ArithmeticOverflow => "Error: Arithmetic overflow",
+            Self::WrongAccountDiscriminator => "Error: Account holds a different kind of data",
+            Self::UnsupportedAccountVersion => "Error: Account layout version is not supported",
+            Self::AccountAlreadyInitialized => "Error: Account is already initialized",
+            Self::ListingReserved => "Error: Listing has a pending buy",
+            Self::IllegalListingTransition => "Error: Listing status does not allow this action",
+            Self::EscrowUnderfunded => "Error: Deal account holds less than the escrowed amount",
+            Self::EscrowPayeeMismatch => "Error: Account does not match the escrow record",
+            Self::LamportImbalance => "Error: Lamports before and after settlement do not balance",
+            Self::ItemIdMismatch => "Error: Item id does not match the listing",
+            Self::NotSeller => "Error: Account is not the seller of this deal",
+            Self::PriceAboveMax => "Error: Listing price is above the buyer max price",
+            Self::ListingRevisionMismatch => "Error: Listing changed since the buyer read it",
+            Self::DealNotShipped => "Error: Seller has not marked the deal shipped",
+            Self::DealAlreadyShipped => "Error: Deal is already shipped",
+            Self::ConfirmWindowOpen => "Error: Buyer confirm window is still open",
+            Self::AcceptDeadlinePassed => "Error: Seller did not ship before the accept deadline",
+            Self::AcceptWindowOpen => "Error: Seller can still ship until the accept deadline",
+            Self::NoArbiter => "Error: Deal has no arbiter",
+            Self::NotArbiter => "Error: Signer is not the arbiter of this deal",
+            Self::NotDealParty => "Error: Signer is neither the buyer nor the seller of this deal",
+            Self::DealDisputed => "Error: Deal is frozen by a dispute",
+            Self::DealNotDisputed => "Error: Deal is not in dispute",
+            Self::InvalidSplit => "Error: Split is more than 10000 basis points",
+            Self::WrongDisputePda => "Error: Dispute record account does not match its seeds",
+            Self::WrongTokenProgram => "Error: Account is not owned by the token program of the mint",
+            Self::InvalidTokenAccount => "Error: Token account or mint data is malformed",
+            Self::UnsupportedMint => "Error: Mint has an extension the escrow can't hold safely",
+            Self::WrongMint => "Error: Mint is not the payment mint of this deal",
+            Self::WrongTokenVault => "Error: Vault token account does not belong to this escrow",
+            Self::TokenAccountMismatch => "Error: Token account has the wrong mint or owner",
+            Self::NotAdmin => "Error: Signer is not the marketplace admin",
+            Self::WrongConfigPda => "Error: Config account does not match its seeds",
+            Self::WrongTreasuryPda => "Error: Treasury account does not match its seeds",
+            Self::InvalidFeeBps => "Error: Fee is more than 10000 basis points",
+            Self::TreasuryInsufficient => "Error: Treasury holds less than the withdrawal or the rent it sponsors",
+            Self::WrongProgramData => "Error: Program data account is not the one of this program",
+            Self::ArbiterNotApproved => "Error: Arbiter is not on the marketplace arbiter list",
+            Self::PriceAboveLimit => "Error: Price is above the marketplace limit",
+            Self::InvalidWindow => "Error: Deal window must be positive",
+            Self::NotPendingAdmin => "Error: Signer is not the proposed admin",
+            Self::MarketplacePaused => "Error: This operation is paused by the marketplace admin",
+            Self::AccountClosed => "Error: Account was closed",
+            Self::AccountNotWritable => "Error: Account should be writable",
+            Self::DuplicateAccount => "Error: The same account is passed for two roles",
+            Self::WrongSystemProgram => "Error: Account is not the system program",
+            Self::InvalidRentPolicy => "Error: Unknown registry rent policy",
+            Self::RentAboveProceeds => "Error: Sale proceeds are too small to pay the registry rent",
+            Self::RentPayerNotSigner => "Error: The party that owes the registry rent did not sign",
+            Self::RentPolicyNotForTokens => "Error: The registry rent policy charges the seller on SELL, a token deal can't",
+        }
+    }
+}
+
+impl std::fmt::Display for MarketplaceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.to_str())
+    }
+}
+
+impl std::error::Error for MarketplaceError {}
@@ -0,0 +1,840 @@
+/*
+    the wire format of the program , the client and the program both use this file so there is only one definition of it.
+    build a MarketplaceInstruction , call pack() and send the bytes , the program call unpack() on the same bytes.
+
+    format (version 2) :
+        [ {version marker} , {tag} , {payload} ]
+    the version marker is INSTRUCTION_VERSION_FLAG | version , all the old tags are < 0x80 so the high bit tell us
+    if the client send the new versioned format or the old one.
+
+    version 1 (deprecated , still decoded for one deprecation cycle , it will be removed in the next version) :
+        same header , but INIT , UPDATE , BUY , SELL and CANCEL carry the version 1 payloads (the *V1 structs below).
+        version 2 gave INIT an arbiter and a payment mint , UPDATE an arbiter , BUY a max price and a revision ,
+        and dropped the seeds SELL and CANCEL used to send. every other tag has the same payload in both versions.
+
+    old format (deprecated , same cycle) :
+        [ {tag} , {payload} ]
+    the payloads of version 1 , it is what the clients sent before the version byte existed.
+
+    every malformed payload come back as its own MarketplaceError (codes 0..=6) , see error.rs.
+    every payload goes through this decoding layer , nothing else in the program should index or cast instruction_data directly.
+    the runtime give us instruction_data at any alignment and the payload start at an odd offset ,
+    so casting it in place with bytemuck::from_bytes panic for any struct that hold a u64 (or u16) field.
+    here we always check the length first and then copy the bytes out with pod_read_unaligned , so nothing here can panic.
+*/
+
+use bytemuck::{Pod, Zeroable};
+use solana_program::msg;
+
+use crate::error::MarketplaceError;
+
+// the version of the wire format that pack() write
+pub const INSTRUCTION_VERSION: u8 = 2;
+// the previous version , its payloads are still decoded (the old tag only format use them too)
+pub const INSTRUCTION_VERSION_V1: u8 = 1;
+// high bit of the first byte , set = versioned format , not set = old tag only format
+pub const INSTRUCTION_VERSION_FLAG: u8 = 0x80;
+
+// max number of description bytes we accept in one WRITE_DESCRIPTION instruction
+pub const DESCRIPTION_CHUNK_MAX: usize = 512;
+
+// the data we get from the client side
+// the description is not part of INIT , a full 1024 byte description push the transaction over the
+// 1232 byte packet limit so INIT only carry the header fields and the description is uploaded in chunks after that
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Pod, Zeroable)]
+pub struct InitData {
+    pub uuid: [u8; 16],
+    pub item_id: [u8; 32],
+    pub title: [u8; 128],
+    pub price: u64,
+    pub seed: [u8; 32], // 31 char we can store in the seed one char is reserve for \0
+    pub arbiter: [u8; 32], // who rule disputes on this listing , all zero = no arbiter
+    pub payment_mint: [u8; 32], // price in this token mint , all zero = lamports. fixed for the life of the listing
+}
+
+//let make a update logic , same as INIT the description is not here it goes through the chunk instructions
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Pod, Zeroable)]
+pub struct UpdateData {
+    pub title: [u8; 128],
+    pub price: u64,
+    pub seed: [u8; 32], // 31 char we can store in the seed one char is reserve for \0
+    pub arbiter: [u8; 32],
+    pub reopen_description: u8, // 1 = clear the description and move the post back to draft so it can be rewritten in chunks
+    pub keep_arbiter: u8, // 1 = leave the listing arbiter as it is , a version 1 UPDATE had no arbiter so it decode to 1
+    pub _padding: [u8; 6],
+}
+
+// header of the WRITE_DESCRIPTION instruction , the chunk bytes (len of them) come right after this header
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Pod, Zeroable)]
+pub struct DescriptionChunk {
+    pub seed: [u8; 32],
+    pub offset: u16,
+    pub len: u16,
+}
+
+// once the description is fully written the owner publish the post and after that the content is locked
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Pod, Zeroable)]
+pub struct PublishData {
+    pub seed: [u8; 32],
+}
+
+// as we need one this in delete logic that is seed because we need to find the pda  and check the person so make a request is realy the post owner  or not beacuse we use the payer key as a part of seed
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Pod, Zeroable)]
+pub struct DeleteData {
+    pub seed: [u8; 32], // 31 char we can store in the seed
+}
+
+//let make a struct for buy , item_id and buyer are what the client expect , BUY check them against the
+//listing and the signer and store the checked values , not these
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Pod, Zeroable)]
+pub struct BuyInit {
+    pub item_id: [u8; 32],
+    pub buyer: [u8; 32],
+    pub seed: [u8; 32],
+    pub max_price: u64,         // BUY fail if the listing price is above this , so the seller can't front run a price raise
+    pub expected_revision: u32, // the listing revision the buyer saw , BUY fail if the listing was edited since
+    pub any_revision: u8,       // 1 = skip the revision check , a version 1 BUY had no revision so it decode to 1
+    pub _padding: [u8; 3],
+}
+
+// the version 1 payloads , decoded into the structs above by unpack_v1 and never packed again
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Pod, Zeroable)]
+pub struct InitDataV1 {
+    pub uuid: [u8; 16],
+    pub item_id: [u8; 32],
+    pub title: [u8; 128],
+    pub price: u64,
+    pub seed: [u8; 32],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Pod, Zeroable)]
+pub struct UpdateDataV1 {
+    pub title: [u8; 128],
+    pub price: u64,
+    pub seed: [u8; 32],
+    pub reopen_description: u8,
+    pub _padding: [u8; 7],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Pod, Zeroable)]
+pub struct BuyInitV1 {
+    pub item_id: [u8; 32],
+    pub buyer: [u8; 32],
+    pub seed: [u8; 32],
+}
+
+// the seeds version 1 SELL and CANCEL sent , the deal account store the keys now so they are read and dropped
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Pod, Zeroable)]
+pub struct DealSeedsV1 {
+    pub first: [u8; 32],  // SELL : seed_post , CANCEL : item_id
+    pub second: [u8; 32], // SELL : seed_buy , CANCEL : seed
+}
+
+// CANCEL , SELL , SHIP , CLAIM and EXPIRE carry no payload , the deal account store the listing and the parties
+// so they check the accounts against those and not against seeds sent by the client
+
+// the seller turn a buyer down , the reason is only logged in the BuyRejected event for the frontend , 0 = no reason given
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Pod, Zeroable)]
+pub struct RejectData {
+    pub reason: u8,
+}
+
+// OPEN_DISPUTE and SUBMIT_EVIDENCE , the hash of whatever the party show the arbiter off chain (all zero = none)
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Pod, Zeroable)]
+pub struct EvidenceData {
+    pub evidence: [u8; 32],
+}
+
+// the arbiter ruling , the seller get seller_bps / 10000 of the escrow and the buyer the rest
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Pod, Zeroable)]
+pub struct ResolveData {
+    pub seller_bps: u16,
+}
+
+// create the marketplace config and treasury , only the upgrade authority of the program can send it
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Pod, Zeroable)]
+pub struct InitConfigData {
+    pub fee_bps: u16,
+}
+
+// replace the marketplace policy , every field is written so the client send the whole config it want
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Pod, Zeroable)]
+pub struct SetConfigData {
+    pub fee_bps: u16,
+    pub registry_rent_policy: u8, // RegistryRentPolicy as u8 , was padding so an old client send 0 = Settler
+    pub _padding: [u8; 5],
+    pub max_price: u64, // 0 = no limit
+    pub accept_window: i64,
+    pub confirm_window: i64,
+    pub arbiters: [[u8; 32]; crate::state::MAX_ARBITERS],
+}
+
+// the admin switch each pause on (1) or off (0) , CANCEL , REJECT and EXPIRE are never paused
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Pod, Zeroable)]
+pub struct SetPauseData {
+    pub listings: u8,    // INIT and PUBLISH
+    pub buys: u8,        // BUY
+    pub settlements: u8, // SELL , CLAIM and RESOLVE_DISPUTE unless it is a full refund
+}
+
+// first step of the admin hand over , all zero call off a pending one
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Pod, Zeroable)]
+pub struct ProposeAdminData {
+    pub new_admin: [u8; 32],
+}
+
+// move collected fees out of the treasury , mint all zero = lamports
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Pod, Zeroable)]
+pub struct WithdrawTreasuryData {
+    pub amount: u64,
+    pub mint: [u8; 32],
+}
+
+// every instruction the program understand , the tag numbers are part of the wire format so never reorder them
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MarketplaceInstruction<'a> {
+    // [payer , pda_account , system_program , config]
+    Init(InitData),
+    // [payer , pda_account , config]
+    Update(UpdateData),
+    // [payer , pda_account]
+    Delete(DeleteData),
+    // [buyer , post_account , deal_account , system_program , config]
+    // + for a token listing [mint , buyer_token_account , vault_token_account , token_program , associated_token_program]
+    Buy(BuyInit),
+    // buyer confirm the receipt , only after SHIP
+    // [seller , buyer , deal_account , post_account , system_program , registry_account , treasury , config]
+    // + for a token deal [mint , vault_token_account , token_program , seller_token_account , treasury_token_account , associated_token_program]
+    Sell,
+    // [buyer , deal_account , post_account]
+    // + for a token deal [mint , vault_token_account , token_program , buyer_token_account] , same for EXPIRE and REJECT
+    Cancel,
+    // [payer , pda_account]
+    WriteDescription {
+        header: DescriptionChunk,
+        chunk: &'a [u8],
+    },
+    // [payer , pda_account , config]
+    Publish(PublishData),
+    // seller mark the deal shipped , start the buyer confirm window
+    // [seller , deal_account]
+    Ship,
+    // seller settle without the buyer once the confirm window is over , same accounts as SELL
+    Claim,
+    // refund a deal the seller never shipped once accept_deadline is over , anyone can send it
+    // [caller , buyer , deal_account , post_account]
+    Expire,
+    // seller refund the buyer and put the listing back to Active
+    // [seller , buyer , deal_account , post_account]
+    Reject(RejectData),
+    // buyer or seller freeze the escrow until the arbiter rule
+    // [party , deal_account]
+    OpenDispute(EvidenceData),
+    // buyer or seller attach a new evidence hash to a disputed deal
+    // [party , deal_account]
+    SubmitEvidence(EvidenceData),
+    // [arbiter , seller , buyer , deal_account , post_account , dispute_record , system_program , config]
+    // + for a token deal [mint , vault_token_account , token_program , seller_token_account , buyer_token_account]
+    ResolveDispute(ResolveData),
+    // [admin , config , treasury , program_data , system_program]
+    InitConfig(InitConfigData),
+    // [admin , config , treasury , destination] + for a token withdraw [mint , treasury_token_account , token_program , destination_token_account]
+    WithdrawTreasury(WithdrawTreasuryData),
+    // [admin , config]
+    SetConfig(SetConfigData),
+    // [admin , config]
+    ProposeAdmin(ProposeAdminData),
+    // the proposed admin take over , no payload
+    // [new_admin , config]
+    AcceptAdmin,
+    // [admin , config]
+    SetPause(SetPauseData),
+}
+
+impl<'a> MarketplaceInstruction<'a> {
+    const INIT: u8 = 0;
+    const UPDATE: u8 = 1;
+    const DELETE: u8 = 2;
+    const BUY: u8 = 3;
+    const SELL: u8 = 4;
+    const CANCEL: u8 = 5;
+    // 6 was HOLD_ACCOUNT , retired with the TEMP account now that the deal account hold the escrow. never reuse it
+    const WRITE_DESCRIPTION: u8 = 7;
+    const PUBLISH: u8 = 8;
+    const SHIP: u8 = 9;
+    const CLAIM: u8 = 10;
+    const EXPIRE: u8 = 11;
+    const REJECT: u8 = 12;
+    const OPEN_DISPUTE: u8 = 13;
+    const SUBMIT_EVIDENCE: u8 = 14;
+    const RESOLVE_DISPUTE: u8 = 15;
+    const INIT_CONFIG: u8 = 16;
+    const WITHDRAW_TREASURY: u8 = 17;
+    const SET_CONFIG: u8 = 18;
+    const PROPOSE_ADMIN: u8 = 19;
+    const ACCEPT_ADMIN: u8 = 20;
+    const SET_PAUSE: u8 = 21;
+
+    // decode the raw instruction_data , versioned or old format
+    pub fn unpack(data: &'a [u8]) -> Result<Self, MarketplaceError> {
+        let (first, rest) = split_tag(data)?;
+        if first & INSTRUCTION_VERSION_FLAG == 0 {
+            // old format , the first byte is the tag itself
+            msg!("Warning: unversioned instruction format is deprecated, send the versioned format");
+            return Self::unpack_v1(first, rest);
+        }
+
+        let version = first & !INSTRUCTION_VERSION_FLAG;
+        let (tag, payload) = split_tag(rest)?;
+        match version {
+            INSTRUCTION_VERSION => Self::unpack_body(tag, payload),
+            INSTRUCTION_VERSION_V1 => {
+                msg!("Warning: instruction format version 1 is deprecated, send version {}", INSTRUCTION_VERSION);
+                Self::unpack_v1(tag, payload)
+            }
+            _ => Err(MarketplaceError::UnsupportedVersion),
+        }
+    }
+
+    // the version 1 payloads , the fields version 2 added get the value that keep the version 1 behaviour :
+    // no arbiter and lamports for INIT , the arbiter left alone by UPDATE , no price or revision check on BUY
+    fn unpack_v1(tag: u8, payload: &'a [u8]) -> Result<Self, MarketplaceError> {
+        match tag {
+            Self::INIT => {
+                let v1 = read_exact::<InitDataV1>(payload)?;
+                Ok(Self::Init(InitData {
+                    uuid: v1.uuid,
+                    item_id: v1.item_id,
+                    title: v1.title,
+                    price: v1.price,
+                    seed: v1.seed,
+                    arbiter: [0; 32],
+                    payment_mint: [0; 32],
+                }))
+            }
+            Self::UPDATE => {
+                let v1 = read_exact::<UpdateDataV1>(payload)?;
+                Ok(Self::Update(UpdateData {
+                    title: v1.title,
+                    price: v1.price,
+                    seed: v1.seed,
+                    arbiter: [0; 32],
+                    reopen_description: v1.reopen_description,
+                    keep_arbiter: 1,
+                    _padding: [0; 6],
+                }))
+            }
+            Self::BUY => {
+                let v1 = read_exact::<BuyInitV1>(payload)?;
+                Ok(Self::Buy(BuyInit {
+                    item_id: v1.item_id,
+                    buyer: v1.buyer,
+                    seed: v1.seed,
+                    max_price: u64::MAX,
+                    expected_revision: 0,
+                    any_revision: 1,
+                    _padding: [0; 3],
+                }))
+            }
+            Self::SELL => read_exact::<DealSeedsV1>(payload).map(|_| Self::Sell),
+            Self::CANCEL => read_exact::<DealSeedsV1>(payload).map(|_| Self::Cancel),
+            _ => Self::unpack_body(tag, payload),
+        }
+    }
+
+    // the current payloads , the header in front of them is already read
+    fn unpack_body(tag: u8, payload: &'a [u8]) -> Result<Self, MarketplaceError> {
+        match tag {
+            Self::INIT => Ok(Self::Init(read_exact(payload)?)),
+            Self::UPDATE => Ok(Self::Update(read_exact(payload)?)),
+            Self::DELETE => Ok(Self::Delete(read_exact(payload)?)),
+            Self::BUY => Ok(Self::Buy(read_exact(payload)?)),
+            Self::SELL => read_empty(payload).map(|_| Self::Sell),
+            Self::CANCEL => read_empty(payload).map(|_| Self::Cancel),
+            Self::WRITE_DESCRIPTION => {
+                let (header, chunk) = read_prefix::<DescriptionChunk>(payload)?;
+                if chunk.len() != header.len as usize {
+                    return Err(MarketplaceError::ChunkLengthMismatch);
+                }
+                Ok(Self::WriteDescription { header, chunk })
+            }
+            Self::PUBLISH => Ok(Self::Publish(read_exact(payload)?)),
+            Self::SHIP => read_empty(payload).map(|_| Self::Ship),
+            Self::CLAIM => read_empty(payload).map(|_| Self::Claim),
+            Self::EXPIRE => read_empty(payload).map(|_| Self::Expire),
+            Self::REJECT => Ok(Self::Reject(read_exact(payload)?)),
+            Self::OPEN_DISPUTE => Ok(Self::OpenDispute(read_exact(payload)?)),
+            Self::SUBMIT_EVIDENCE => Ok(Self::SubmitEvidence(read_exact(payload)?)),
+            Self::RESOLVE_DISPUTE => Ok(Self::ResolveDispute(read_exact(payload)?)),
+            Self::INIT_CONFIG => Ok(Self::InitConfig(read_exact(payload)?)),
+            Self::WITHDRAW_TREASURY => Ok(Self::WithdrawTreasury(read_exact(payload)?)),
+            Self::SET_CONFIG => Ok(Self::SetConfig(read_exact(payload)?)),
+            Self::PROPOSE_ADMIN => Ok(Self::ProposeAdmin(read_exact(payload)?)),
+            Self::ACCEPT_ADMIN => read_empty(payload).map(|_| Self::AcceptAdmin),
+            Self::SET_PAUSE => Ok(Self::SetPause(read_exact(payload)?)),
+            _ => Err(MarketplaceError::UnknownInstruction),
+        }
+    }
+
+    // encode into the current versioned format
+    pub fn pack(&self) -> Vec<u8> {
+        let mut buf = vec![INSTRUCTION_VERSION_FLAG | INSTRUCTION_VERSION];
+        match self {
+            Self::Init(data) => {
+                buf.push(Self::INIT);
+                buf.extend_from_slice(bytemuck::bytes_of(data));
+            }
+            Self::Update(data) => {
+                buf.push(Self::UPDATE);
+                buf.extend_from_slice(bytemuck::bytes_of(data));
+            }
+            Self::Delete(data) => {
+                buf.push(Self::DELETE);
+                buf.extend_from_slice(bytemuck::bytes_of(data));
+            }
+            Self::Buy(data) => {
+                buf.push(Self::BUY);
+                buf.extend_from_slice(bytemuck::bytes_of(data));
+            }
+            Self::Sell => buf.push(Self::SELL),
+            Self::Cancel => buf.push(Self::CANCEL),
+            Self::WriteDescription { header, chunk } => {
+                buf.push(Self::WRITE_DESCRIPTION);
+                buf.extend_from_slice(bytemuck::bytes_of(header));
+                buf.extend_from_slice(chunk);
+            }
+            Self::Publish(data) => {
+                buf.push(Self::PUBLISH);
+                buf.extend_from_slice(bytemuck::bytes_of(data));
+            }
+            Self::Ship => buf.push(Self::SHIP),
+            Self::Claim => buf.push(Self::CLAIM),
+            Self::Expire => buf.push(Self::EXPIRE),
+            Self::Reject(data) => {
+                buf.push(Self::REJECT);
+                buf.extend_from_slice(bytemuck::bytes_of(data));
+            }
+            Self::OpenDispute(data) => {
+                buf.push(Self::OPEN_DISPUTE);
+                buf.extend_from_slice(bytemuck::bytes_of(data));
+            }
+            Self::SubmitEvidence(data) => {
+                buf.push(Self::SUBMIT_EVIDENCE);
+                buf.extend_from_slice(bytemuck::bytes_of(data));
+            }
+            Self::ResolveDispute(data) => {
+                buf.push(Self::RESOLVE_DISPUTE);
+                buf.extend_from_slice(bytemuck::bytes_of(data));
+            }
+            Self::InitConfig(data) => {
+                buf.push(Self::INIT_CONFIG);
+                buf.extend_from_slice(bytemuck::bytes_of(data));
+            }
+            Self::WithdrawTreasury(data) => {
+                buf.push(Self::WITHDRAW_TREASURY);
+                buf.extend_from_slice(bytemuck::bytes_of(data));
+            }
+            Self::SetConfig(data) => {
+                buf.push(Self::SET_CONFIG);
+                buf.extend_from_slice(bytemuck::bytes_of(data));
+            }
+            Self::ProposeAdmin(data) => {
+                buf.push(Self::PROPOSE_ADMIN);
+                buf.extend_from_slice(bytemuck::bytes_of(data));
+            }
+            Self::AcceptAdmin => buf.push(Self::ACCEPT_ADMIN),
+            Self::SetPause(data) => {
+                buf.push(Self::SET_PAUSE);
+                buf.extend_from_slice(bytemuck::bytes_of(data));
+            }
+        }
+        buf
+    }
+}
+
+// split the first byte (tag) from the rest of the payload
+fn split_tag(data: &[u8]) -> Result<(u8, &[u8]), MarketplaceError> {
+    data.split_first()
+        .map(|(tag, rest)| (*tag, rest))
+        .ok_or(MarketplaceError::EmptyInstruction)
+}
+
+// the payload must be exactly one T , copied out so alignment don't matter
+fn read_exact<T: Pod>(payload: &[u8]) -> Result<T, MarketplaceError> {
+    let size = std::mem::size_of::<T>();
+    if payload.len() < size {
+        return Err(MarketplaceError::PayloadTooShort);
+    }
+    if payload.len() > size {
+        return Err(MarketplaceError::PayloadTooLong);
+    }
+    Ok(bytemuck::pod_read_unaligned::<T>(payload))
+}
+
+// for the instructions that have no payload at all
+fn read_empty(payload: &[u8]) -> Result<(), MarketplaceError> {
+    if !payload.is_empty() {
+        return Err(MarketplaceError::PayloadTooLong);
+    }
+    Ok(())
+}
+
+// the payload start with a T header and the rest is handed back to the caller as it is
+fn read_prefix<T: Pod>(payload: &[u8]) -> Result<(T, &[u8]), MarketplaceError> {
+    let size = std::mem::size_of::<T>();
+    if payload.len() < size {
+        return Err(MarketplaceError::PayloadTooShort);
+    }
+    let (head, rest) = payload.split_at(size);
+    Ok((bytemuck::pod_read_unaligned::<T>(head), rest))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // one of every variant , with non zero fields so a wrong offset show up
+    fn every_variant(chunk: &[u8]) -> Vec<MarketplaceInstruction<'_>> {
+        let seed = [7; 32];
+        let mut init = InitData::zeroed();
+        init.seed = seed;
+        init.price = 1_000_000;
+        init.title[0] = b'x';
+        let mut update = UpdateData::zeroed();
+        update.seed = seed;
+        update.price = 42;
+        update.reopen_description = 1;
+        let mut buy = BuyInit::zeroed();
+        buy.seed = seed;
+        buy.max_price = u64::MAX;
+        buy.expected_revision = 3;
+        let mut set_config = SetConfigData::zeroed();
+        set_config.fee_bps = 250;
+        set_config.registry_rent_policy = 3;
+        set_config.max_price = 9;
+        set_config.accept_window = -1;
+        set_config.confirm_window = 60;
+        set_config.arbiters[1] = [2; 32];
+
+        vec![
+            MarketplaceInstruction::Init(init),
+            MarketplaceInstruction::Update(update),
+            MarketplaceInstruction::Delete(DeleteData { seed }),
+            MarketplaceInstruction::Buy(buy),
+            MarketplaceInstruction::Sell,
+            MarketplaceInstruction::Cancel,
+            MarketplaceInstruction::WriteDescription {
+                header: DescriptionChunk {
+                    seed,
+                    offset: 512,
+                    len: chunk.len() as u16,
+                },
+                chunk,
+            },
+            MarketplaceInstruction::Publish(PublishData { seed }),
+            MarketplaceInstruction::Ship,
+            MarketplaceInstruction::Claim,
+            MarketplaceInstruction::Expire,
+            MarketplaceInstruction::Reject(RejectData { reason: 4 }),
+            MarketplaceInstruction::OpenDispute(EvidenceData { evidence: [1; 32] }),
+            MarketplaceInstruction::SubmitEvidence(EvidenceData { evidence: [2; 32] }),
+            MarketplaceInstruction::ResolveDispute(ResolveData { seller_bps: 10_000 }),
+            MarketplaceInstruction::InitConfig(InitConfigData { fee_bps: 100 }),
+            MarketplaceInstruction::WithdrawTreasury(WithdrawTreasuryData {
+                amount: 5,
+                mint: [3; 32],
+            }),
+            MarketplaceInstruction::SetConfig(set_config),
+            MarketplaceInstruction::ProposeAdmin(ProposeAdminData { new_admin: [4; 32] }),
+            MarketplaceInstruction::AcceptAdmin,
+            MarketplaceInstruction::SetPause(SetPauseData {
+                listings: 1,
+                buys: 0,
+                settlements: 1,
+            }),
+        ]
+    }
+
+    #[test]
+    fn pack_unpack_round_trip() {
+        let chunk = [9u8; 100];
+        for ix in every_variant(&chunk) {
+            let packed = ix.pack();
+            assert_eq!(packed[0], INSTRUCTION_VERSION_FLAG | INSTRUCTION_VERSION);
+            assert_eq!(MarketplaceInstruction::unpack(&packed), Ok(ix));
+        }
+    }
+
+    // the tags whose payload changed in version 2 , checked on their own in version_1_payloads
+    fn changed_in_v2(ix: &MarketplaceInstruction) -> bool {
+        matches!(
+            ix,
+            MarketplaceInstruction::Init(_)
+                | MarketplaceInstruction::Update(_)
+                | MarketplaceInstruction::Buy(_)
+                | MarketplaceInstruction::Sell
+                | MarketplaceInstruction::Cancel
+        )
+    }
+
+    #[test]
+    fn legacy_tag_only_format() {
+        let chunk = [9u8; 3];
+        for ix in every_variant(&chunk).into_iter().filter(|ix| !changed_in_v2(ix)) {
+            let packed = ix.pack();
+            // the old format is the same bytes without the version marker
+            assert_eq!(MarketplaceInstruction::unpack(&packed[1..]), Ok(ix));
+            // and version 1 the same bytes with the version 1 marker
+            let mut v1 = packed.clone();
+            v1[0] = INSTRUCTION_VERSION_FLAG | INSTRUCTION_VERSION_V1;
+            assert_eq!(MarketplaceInstruction::unpack(&v1), Ok(ix));
+        }
+    }
+
+    #[test]
+    fn version_1_payloads() {
+        let seed = [7; 32];
+        let init = InitDataV1 {
+            uuid: [1; 16],
+            item_id: [2; 32],
+            title: [3; 128],
+            price: 1_000_000,
+            seed,
+        };
+        let update = UpdateDataV1 {
+            title: [4; 128],
+            price: 42,
+            seed,
+            reopen_description: 1,
+            _padding: [0; 7],
+        };
+        let buy = BuyInitV1 {
+            item_id: [2; 32],
+            buyer: [5; 32],
+            seed,
+        };
+        let seeds = DealSeedsV1 {
+            first: [6; 32],
+            second: seed,
+        };
+
+        // no arbiter and lamports , the arbiter kept , no price or revision check
+        let init_v2 = InitData {
+            uuid: init.uuid,
+            item_id: init.item_id,
+            title: init.title,
+            price: init.price,
+            seed,
+            arbiter: [0; 32],
+            payment_mint: [0; 32],
+        };
+        let update_v2 = UpdateData {
+            title: update.title,
+            price: update.price,
+            seed,
+            arbiter: [0; 32],
+            reopen_description: 1,
+            keep_arbiter: 1,
+            _padding: [0; 6],
+        };
+        let buy_v2 = BuyInit {
+            item_id: buy.item_id,
+            buyer: buy.buyer,
+            seed,
+            max_price: u64::MAX,
+            expected_revision: 0,
+            any_revision: 1,
+            _padding: [0; 3],
+        };
+
+        let cases: [(u8, &[u8], MarketplaceInstruction); 5] = [
+            (0, bytemuck::bytes_of(&init), MarketplaceInstruction::Init(init_v2)),
+            (1, bytemuck::bytes_of(&update), MarketplaceInstruction::Update(update_v2)),
+            (3, bytemuck::bytes_of(&buy), MarketplaceInstruction::Buy(buy_v2)),
+            (4, bytemuck::bytes_of(&seeds), MarketplaceInstruction::Sell),
+            (5, bytemuck::bytes_of(&seeds), MarketplaceInstruction::Cancel),
+        ];
+        for (tag, payload, expected) in cases {
+            let mut legacy = vec![tag];
+            legacy.extend_from_slice(payload);
+            assert_eq!(MarketplaceInstruction::unpack(&legacy), Ok(expected));
+            let mut v1 = vec![INSTRUCTION_VERSION_FLAG | INSTRUCTION_VERSION_V1];
+            v1.extend_from_slice(&legacy);
+            assert_eq!(MarketplaceInstruction::unpack(&v1), Ok(expected));
+            // a version 2 payload behind a version 1 marker is the wrong size , never read as something else
+            assert!(MarketplaceInstruction::unpack(&[&v1[..2], &expected.pack()[2..]].concat()).is_err());
+        }
+    }
+
+    // what the off-chain services sent before the version byte , hand encoded : the tag then every field back to
+    // back , integers little endian. built byte by byte and not from our structs so a moved field show up
+    fn field(bytes: &[u8], len: usize) -> Vec<u8> {
+        let mut out = bytes.to_vec();
+        out.resize(len, 0);
+        out
+    }
+
+    #[test]
+    fn pre_version_fixtures() {
+        let init = [
+            &[0u8][..],                      // INIT
+            &field(b"uuid-0001", 16),        // uuid
+            &field(b"item-42", 32),          // item_id
+            &field(b"red bike", 128),        // title
+            &1_500_000_000u64.to_le_bytes(), // price
+            &field(b"listing-seed", 32),     // seed
+        ]
+        .concat();
+        assert_eq!(init.len(), 217);
+        match MarketplaceInstruction::unpack(&init) {
+            Ok(MarketplaceInstruction::Init(data)) => {
+                assert_eq!(data.uuid[..9], *b"uuid-0001");
+                assert_eq!(data.item_id[..7], *b"item-42");
+                assert_eq!(data.title[..8], *b"red bike");
+                assert_eq!(data.price, 1_500_000_000);
+                assert_eq!(data.seed[..12], *b"listing-seed");
+                assert_eq!((data.arbiter, data.payment_mint), ([0; 32], [0; 32]));
+            }
+            other => panic!("INIT fixture gave {:?}", other),
+        }
+
+        let buyer = [0xb7u8; 32];
+        let buy = [
+            &[3u8][..],               // BUY
+            &field(b"item-42", 32),   // item_id
+            &buyer,                   // buyer
+            &field(b"deal-seed", 32), // seed
+        ]
+        .concat();
+        assert_eq!(buy.len(), 97);
+        match MarketplaceInstruction::unpack(&buy) {
+            Ok(MarketplaceInstruction::Buy(data)) => {
+                assert_eq!(data.item_id[..7], *b"item-42");
+                assert_eq!(data.buyer, buyer);
+                assert_eq!(data.seed[..9], *b"deal-seed");
+                assert_eq!((data.max_price, data.any_revision), (u64::MAX, 1));
+            }
+            other => panic!("BUY fixture gave {:?}", other),
+        }
+
+        let sell = [
+            &[4u8][..],                  // SELL
+            &field(b"listing-seed", 32), // seed_post
+            &field(b"deal-seed", 32),    // seed_buy
+        ]
+        .concat();
+        assert_eq!(sell.len(), 65);
+        assert_eq!(MarketplaceInstruction::unpack(&sell), Ok(MarketplaceInstruction::Sell));
+
+        // the same bytes behind the version 1 marker decode the same
+        for fixture in [&init, &buy, &sell] {
+            let v1 = [&[INSTRUCTION_VERSION_FLAG | INSTRUCTION_VERSION_V1][..], fixture].concat();
+            assert_eq!(MarketplaceInstruction::unpack(&v1), MarketplaceInstruction::unpack(fixture));
+        }
+
+        // the first INIT carried the 1024 byte description too , 1241 bytes never fit a 1232 byte transaction so
+        // no client could send it. it is refused , not read as something else
+        let with_description = [&init[..177], &[0u8; 1024][..], &init[177..]].concat();
+        assert_eq!(
+            MarketplaceInstruction::unpack(&with_description),
+            Err(MarketplaceError::PayloadTooLong)
+        );
+    }
+
+    #[test]
+    fn misaligned_payload() {
+        let chunk = [1u8; 7];
+        for ix in every_variant(&chunk) {
+            let packed = ix.pack();
+            for shift in 1..8 {
+                let mut buf = vec![0u8; shift];
+                buf.extend_from_slice(&packed);
+                assert_eq!(MarketplaceInstruction::unpack(&buf[shift..]), Ok(ix));
+            }
+        }
+    }
+
+    #[test]
+    fn empty_instruction() {
+        assert_eq!(MarketplaceInstruction::unpack(&[]), Err(MarketplaceError::EmptyInstruction));
+        // a version marker with no tag after it
+        assert_eq!(
+            MarketplaceInstruction::unpack(&[INSTRUCTION_VERSION_FLAG | INSTRUCTION_VERSION]),
+            Err(MarketplaceError::EmptyInstruction)
+        );
+    }
+
+    #[test]
+    fn short_and_long_payloads() {
+        let chunk = [5u8; 10];
+        for ix in every_variant(&chunk) {
+            let packed = ix.pack();
+            let mut long = packed.clone();
+            long.push(0);
+            match ix {
+                // the chunk bytes are counted by the header , one more byte is a length mismatch
+                MarketplaceInstruction::WriteDescription { .. } => assert_eq!(
+                    MarketplaceInstruction::unpack(&long),
+                    Err(MarketplaceError::ChunkLengthMismatch)
+                ),
+                _ => assert_eq!(
+                    MarketplaceInstruction::unpack(&long),
+                    Err(MarketplaceError::PayloadTooLong)
+                ),
+            }
+
+            if packed.len() == 2 {
+                continue; // no payload to cut
+            }
+            for len in 2..packed.len() {
+                let result = MarketplaceInstruction::unpack(&packed[..len]);
+                assert!(
+                    matches!(
+                        result,
+                        Err(MarketplaceError::PayloadTooShort | MarketplaceError::ChunkLengthMismatch)
+                    ),
+                    "{:?} cut at {} gave {:?}",
+                    ix,
+                    len,
+                    result
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn unknown_tag_and_version() {
+        let version = INSTRUCTION_VERSION_FLAG | INSTRUCTION_VERSION;
+        // 6 is the retired HOLD_ACCOUNT
+        for tag in [6u8, 22, 0x7f] {
+            assert_eq!(
+                MarketplaceInstruction::unpack(&[version, tag]),
+                Err(MarketplaceError::UnknownInstruction)
+            );
+        }
+        for version in [0u8, 3, 0x7f] {
+            assert_eq!(
+                MarketplaceInstruction::unpack(&[INSTRUCTION_VERSION_FLAG | version, 4]),
+                Err(MarketplaceError::UnsupportedVersion)
+            );
+        }
+    }
+}
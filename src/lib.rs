@@ -1,19 +1,38 @@
-use std::time::Instant;
-
 //load all the modules that we need to use in our program
-use bytemuck::{Pod, Zeroable};
-use solana_program::entrypoint;
 use solana_program::program::invoke;
 use solana_program::{
-    account_info::{AccountInfo, next_account_info},
+    account_info::AccountInfo,
+    clock::Clock,
     entrypoint::ProgramResult,
     msg,
-    program::invoke_signed,
     program_error::ProgramError,
+    program::invoke_signed,
     pubkey::Pubkey,
     sysvar::{Sysvar, rent::Rent},
 };
-use solana_system_interface::instruction;
+use solana_system_interface::instruction as system_instruction;
+
+pub mod accounts;
+pub mod error;
+pub mod instruction;
+pub mod state;
+pub mod token;
+use crate::accounts::{Account, Accounts};
+use crate::error::MarketplaceError;
+use crate::instruction::{
+    BuyInit, DESCRIPTION_CHUNK_MAX, DeleteData, DescriptionChunk, InitConfigData, InitData,
+    EvidenceData, MarketplaceInstruction, ProposeAdminData, PublishData, RejectData, ResolveData,
+    SetConfigData, SetPauseData, UpdateData, WithdrawTreasuryData,
+};
+use crate::state::{
+    ACCEPT_WINDOW, ConfigData, DELIVERY_CONFIRM_WINDOW, DealData, DealStatus, DisputeRecordData,
+    ImmutableRegistryData, InitAccountData, ListingStatus, MAX_FEE_BPS,
+    RegistryRentPolicy, RentShares, TreasuryData, load, load_mut,
+};
+
+// the loader that own upgradeable programs , its ProgramData account name the upgrade authority
+const BPF_LOADER_UPGRADEABLE_ID: Pubkey =
+    solana_program::pubkey!("BPFLoaderUpgradeab1e11111111111111111111111");
 
 /*
   struct -
@@ -26,313 +45,151 @@ use solana_system_interface::instruction;
 
 /*
     as we start our programing first we thought what we are making we are making a olx contract me on-chain side logic about out olx dapp
-    as we know we cant pass mutliple instruction in  single enterypoint so we have to make a enum but wait enum and bytemuck dont go well together
-    so we have to go with the struct where we define a u8 variable as a instruction identifier and then we can define the other variable that we want to pass
-    inside the struct and then make a impl block for that struct and inside that impl block we can make a function that will convert the u8 variable to enum
+    all the instruction and its payload struct live in instruction.rs as MarketplaceInstruction , the client use the same
+    enum with pack() and the program decode it with unpack() so both side share one definition of the wire format
 */
 
-#[repr(transparent)]
-#[derive(Clone, Copy, Pod, Zeroable)]
-struct State(u8);
-
-impl State {
-    const INIT: u8 = 0;
-    const UPDATE: u8 = 1;
-    const DELETE: u8 = 2;
-    const BUY: u8 = 3;
-    const SELL: u8 = 4;
-    const CANCEL: u8 = 5;
-    const HOLD_ACCOUNT: u8 = 6;
-
-    fn from_u8(value: u8) -> Result<Self, ProgramError> {
-        match value {
-            Self::INIT => Ok(State(Self::INIT)),
-            Self::UPDATE => Ok(State(Self::UPDATE)),
-            Self::DELETE => Ok(State(Self::DELETE)),
-            Self::BUY => Ok(State(Self::BUY)),
-            Self::SELL => Ok(State(Self::SELL)),
-            Self::CANCEL => Ok(State(Self::CANCEL)),
-            Self::HOLD_ACCOUNT => Ok(State(Self::HOLD_ACCOUNT)),
-            _ => Err(ProgramError::InvalidInstructionData),
-        }
-    }
-}
-
-// let go one by one first we implement the INIT data structer then we go to the other one
-
-// the data we get from the client side
-#[repr(C)]
-#[derive(Clone, Copy, Pod, Zeroable)]
-struct InitData {
-    uuid: [u8; 16],
-    item_id: [u8; 32],
-    title: [u8; 128],
-    description: [u8; 1024],
-    price: u64,
-    seed: [u8; 32], // 31 char we can store in the seed one char is reserve for \0
-}
-
-// the main data structer for an account data that we store in side the account
-#[repr(C)]
-#[derive(Clone, Copy, Pod, Zeroable)]
-struct InitAccountData {
-    item_id: [u8; 32],
-    title: [u8; 128],
-    description: [u8; 1024],
-    price: u64,
-    payer: [u8; 32],
-}
-
-//let make a update logic so this struct all going to be same as the InitAccountData
-#[repr(C)]
-#[derive(Clone, Copy, Pod, Zeroable)]
-struct UpdateData {
-    title: [u8; 128],
-    description: [u8; 1024],
-    price: u64,
-    seed: [u8; 32], // 31 char we can store in the seed one char is reserve for \0
-}
-
-// as we need one this in delete logic that is seed because we need to find the pda  and check the person so make a request is realy the post owner  or not beacuse we use the payer key as a part of seed
-#[repr(C)]
-#[derive(Clone, Copy, Pod, Zeroable)]
-struct DeleteData {
-    seed: [u8; 32], // 31 char we can store in the seed
-}
-
-//let make a struct for buy
-#[repr(C)]
-#[derive(Clone, Copy, Pod, Zeroable)]
-struct BuyInit {
-    item_id: [u8; 32],
-    buyer: [u8; 32],
-    seed: [u8; 32],
-}
-
-#[repr(C)]
-#[derive(Clone, Copy, Pod, Zeroable)]
-struct BuyData {
-    item_id: [u8; 32],
-    buyer: [u8; 32],
-    seller: [u8; 32],
-    price: u64,
-}
-
-#[repr(C)]
-#[derive(Clone, Copy, Pod, Zeroable)]
-struct CancelBuy {
-    item_id: [u8; 32],
-    seed: [u8; 32],
-}
-
-#[repr(C)]
-#[derive(Clone, Copy, Pod, Zeroable)]
-struct SellData {
-    seed_post: [u8; 32],
-    seed_buy: [u8; 32],
-}
-
-#[repr(C)]
-#[derive(Clone, Copy, Pod, Zeroable)]
-struct ImmutableRegistryData {
-    item_id: [u8; 32],
-    buyer: [u8; 32],
-    seller: [u8; 32],
-    price: u64,
-    title: [u8; 128],
-    description: [u8; 1024],
-    timestamp: u64,
-}
-
-//we make two thing normal sol holder and and account where we store the all the immutable proof that buy happend
-//===============================================================================================
-#[repr(transparent)]
-#[derive(Clone,Copy,Pod,Zeroable)]
-struct HoldState(u8);
-
-impl HoldState {
-    const MONEY_HOLDER : u8 = 0;
-    const TEMP_MONEY_HOLDER : u8 = 1;
-    const BUY_INFO_HOLDER: u8 = 2;
+//call the macro entrypoint to define the entry point of the program
+// clients that depend on this crate for the instruction types turn on no-entrypoint so they don't get a second entrypoint
+#[cfg(not(feature = "no-entrypoint"))]
+solana_program::entrypoint!(process_instruction);
 
-    fn from_u8(value : u8)-> Result<Self , ProgramError>{
-        match value{
-            Self::MONEY_HOLDER => Ok(HoldState(Self::MONEY_HOLDER)),
-            Self::TEMP_MONEY_HOLDER => Ok(HoldState(Self::TEMP_MONEY_HOLDER)),
-            Self::BUY_INFO_HOLDER => Ok(HoldState(Self::BUY_INFO_HOLDER)),
-            _ => Err(ProgramError::InvalidInstructionData)
-        }
+// we have to make a entery point fn that will call by macro entrypoint and also handle which instruction to call
 
+pub fn process_instruction(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    instruction_data: &[u8], // MarketplaceInstruction::pack() bytes
+) -> ProgramResult {
+    let result = dispatch_instruction(program_id, accounts, instruction_data);
+    // print the failure in words , the client still get the stable MarketplaceError code as ProgramError::Custom
+    if let Err(error) = &result {
+        msg!("{}", error.to_str::<MarketplaceError>());
     }
-    
-}
-
-
-#[repr(C)]
-#[derive(Clone, Copy, Pod, Zeroable)]
-struct MoneyHolder{
-    title : [u8;32]
-}
-
-
-#[repr(C)]
-#[derive(Clone, Copy, Pod, Zeroable)]
-struct TempMoneyHolder{
-    title : [u8;32],
-    buyer : [u8;32],
-    seller : [u8;32]
+    result
 }
 
-#[repr(C)]
-#[derive(Clone, Copy, Pod, Zeroable)]
-struct Info{
-    title : [u8;32],
-}
-
-
-
-
-//===============================================================================================
-
-//call the macro entrypoint to define the entry point of the program
-entrypoint!(process_instruction);
-
-// we have to make a entery point fn that will call by macro entrypoint and also handle which instruction to call
-
-fn process_instruction(
+fn dispatch_instruction(
     program_id: &Pubkey,
     accounts: &[AccountInfo],
-    instruction_data: &[u8], // [ {state}]
+    instruction_data: &[u8],
 ) -> ProgramResult {
-    let instruction = State::from_u8(instruction_data[0])?;
-    match instruction.0 {
-        State::INIT => {
+    // every payload goes through MarketplaceInstruction::unpack , it check the length exactly and copy
+    // the bytes out so a short , long or misaligned payload come back as an error instead of a panic
+    match MarketplaceInstruction::unpack(instruction_data)? {
+        MarketplaceInstruction::Init(data) => {
             msg!("Instruction: INIT");
-            if instruction_data[1..].len() != std::mem::size_of::<InitData>() {
-                return Err(ProgramError::InvalidInstructionData);
-            }
-            let data = bytemuck::from_bytes::<InitData>(&instruction_data[1..]);
-            process_init(program_id, accounts, data) // pass the rest of the data
+            process_init(program_id, accounts, &data) // pass the rest of the data
         }
-        State::UPDATE => {
+        MarketplaceInstruction::Update(data) => {
             msg!("Instruction: UPDATE");
-            if instruction_data[1..].len() != std::mem::size_of::<UpdateData>() {
-                return Err(ProgramError::InvalidInstructionData);
-            }
-            let data = bytemuck::from_bytes::<UpdateData>(&instruction_data[1..]);
-            update_account_data(program_id, accounts, data) // pass the rest of the data
+            update_account_data(program_id, accounts, &data) // pass the rest of the data
         }
-
-        State::DELETE => {
+        MarketplaceInstruction::Delete(data) => {
             msg!("Instruction: DELETE");
-            if instruction_data[1..].len() != std::mem::size_of::<DeleteData>() {
-                return Err(ProgramError::InvalidInstructionData);
-            }
-            let data = bytemuck::from_bytes::<DeleteData>(&instruction_data[1..]);
             delete_account_data(program_id, accounts, &data) // pass the rest of the data
         }
-
-        State::BUY => {
+        MarketplaceInstruction::Buy(data) => {
             msg!("Instruction: BUY");
-            if instruction_data[1..].len() != std::mem::size_of::<BuyInit>() {
-                return Err(ProgramError::InvalidInstructionData);
-            }
-            let data = bytemuck::from_bytes::<BuyInit>(&instruction_data[1..]);
-            buy_item(program_id, accounts, data)
+            buy_item(program_id, accounts, &data)
         }
-        State::SELL => {
+        MarketplaceInstruction::Sell => {
             msg!("Instruction: SELL");
-            if instruction_data[1..].len() != std::mem::size_of::<SellData>(){
-                return Err(ProgramError::InvalidAccountData);
-            }
-            let data = bytemuck::from_bytes::<SellData>(&instruction_data[1..]);
-            sell_item(program_id, accounts, data)
-            
+            sell_item(program_id, accounts, false)
         }
-        State::CANCEL => {
+        MarketplaceInstruction::Cancel => {
             msg!("Instruction: CANCEL");
-            if instruction_data[1..].len() != std::mem::size_of::<CancelBuy>() {
-                return Err(ProgramError::InvalidInstructionData);
-            }
-            let data = bytemuck::from_bytes::<CancelBuy>(&instruction_data[1..]);
-            cancel_buy(program_id, accounts, data)
-        }
-        State::HOLD_ACCOUNT => {
-            msg!("Instruction: HOLD_ACCOUNT");
-            if instruction_data[1..].len() >= std::mem::size_of::<HoldState>(){
-                match instruction_data[2]{
-                    HoldState::MONEY_HOLDER => {
-                        if instruction_data[2..].len() < std::mem::size_of::<MoneyHolder>(){
-                            return Err(ProgramError::InvalidArgument)
-                        }
-
-                        let data = bytemuck::from_bytes::<MoneyHolder>( &instruction_data[2..]);
-                        return money_holder(program_id, accounts, data)
-                        
-                    }
-                    HoldState::TEMP_MONEY_HOLDER => {
-                        if instruction_data[2..].len() < std::mem::size_of::<TempMoneyHolder>(){
-                            return Err(ProgramError::InvalidArgument)
-                        }
-
-                        let data = bytemuck::from_bytes::<TempMoneyHolder>( &instruction_data[2..]);
-                        return temp_money_holder(program_id, accounts, data)
-                        
-                    }
-                    HoldState::BUY_INFO_HOLDER => {
-                       if instruction_data[2..].len() < std::mem::size_of::<Info>(){
-                            return Err(ProgramError::InvalidArgument)
-                        }
-
-                        let data = bytemuck::from_bytes::<Info>( &instruction_data[2..]);
-                        return info_account(program_id, accounts, data)
-                    }
-                    _ => {
-                        msg!("Error: Invalid HoldState argument");
-                    }
-                }
-            }
-            Err(ProgramError::InvalidInstructionData)
-            
+            cancel_buy(program_id, accounts)
+        }
+        MarketplaceInstruction::WriteDescription { header, chunk } => {
+            msg!("Instruction: WRITE_DESCRIPTION");
+            write_description_chunk(program_id, accounts, &header, chunk)
+        }
+        MarketplaceInstruction::Publish(data) => {
+            msg!("Instruction: PUBLISH");
+            publish_post(program_id, accounts, &data)
+        }
+        MarketplaceInstruction::Ship => {
+            msg!("Instruction: SHIP");
+            ship_item(program_id, accounts)
+        }
+        MarketplaceInstruction::Claim => {
+            msg!("Instruction: CLAIM");
+            sell_item(program_id, accounts, true)
+        }
+        MarketplaceInstruction::Expire => {
+            msg!("Instruction: EXPIRE");
+            expire_buy(program_id, accounts)
+        }
+        MarketplaceInstruction::Reject(data) => {
+            msg!("Instruction: REJECT");
+            reject_buy(program_id, accounts, &data)
+        }
+        MarketplaceInstruction::OpenDispute(data) => {
+            msg!("Instruction: OPEN_DISPUTE");
+            open_dispute(program_id, accounts, &data)
+        }
+        MarketplaceInstruction::SubmitEvidence(data) => {
+            msg!("Instruction: SUBMIT_EVIDENCE");
+            submit_evidence(program_id, accounts, &data)
+        }
+        MarketplaceInstruction::ResolveDispute(data) => {
+            msg!("Instruction: RESOLVE_DISPUTE");
+            resolve_dispute(program_id, accounts, &data)
+        }
+        MarketplaceInstruction::InitConfig(data) => {
+            msg!("Instruction: INIT_CONFIG");
+            init_config(program_id, accounts, &data)
+        }
+        MarketplaceInstruction::WithdrawTreasury(data) => {
+            msg!("Instruction: WITHDRAW_TREASURY");
+            withdraw_treasury(program_id, accounts, &data)
+        }
+        MarketplaceInstruction::SetConfig(data) => {
+            msg!("Instruction: SET_CONFIG");
+            set_config(program_id, accounts, &data)
+        }
+        MarketplaceInstruction::ProposeAdmin(data) => {
+            msg!("Instruction: PROPOSE_ADMIN");
+            propose_admin(program_id, accounts, &data)
+        }
+        MarketplaceInstruction::AcceptAdmin => {
+            msg!("Instruction: ACCEPT_ADMIN");
+            accept_admin(program_id, accounts)
+        }
+        MarketplaceInstruction::SetPause(data) => {
+            msg!("Instruction: SET_PAUSE");
+            set_pause(program_id, accounts, &data)
         }
-        _ => Err(ProgramError::InvalidInstructionData),
     }
 }
 
 fn process_init(
     program_id: &Pubkey,
-    accounts: &[AccountInfo], //[payer , unsign_account , system_program, lookup_table_account]
+    accounts: &[AccountInfo], //[payer , unsign_account , system_program , config] + optional [rent_funder]
     ix_data: &InitData,
 ) -> ProgramResult {
-    let account_info_iter = &mut accounts.iter();
-    let payer = next_account_info(account_info_iter)?; // the account that will pay for the rent
-    let unsigned_account = next_account_info(account_info_iter)?; // the account that will be created and store the data
-    let system_program = next_account_info(account_info_iter)?; // the system program account
-
-    if !payer.is_signer {
-        msg!("Error: Payer account should be a signer");
-        return Err(ProgramError::InvalidArgument);
-    }
-
-    // You can add this back to process_init if you want extra validation
-    if !unsigned_account.is_signer {
-        msg!("Error: Unsigned account is not a signer");
-        return Err(ProgramError::MissingRequiredSignature);
-    }
+    let mut accounts = Accounts::new(program_id, accounts);
+    let payer = accounts.next_account()?.signer()?.writable()?.info(); // the owner of the listing , in its seeds
+    // the account that will be created and store the data , a PDA so it can't sign , the seeds prove it
+    let (unsigned_account, bump_seed) = accounts.next_account()?.writable()?.find_pda(
+        &[b"INIT", &ix_data.seed, payer.key.as_ref()],
+        MarketplaceError::WrongListingPda,
+    )?;
+    let unsigned_account = unsigned_account.info();
+    let system_program = accounts.next_account()?.system_program()?.info();
+    let config = load_config(accounts.next_account()?)?; // the marketplace policy the listing must follow
+    let funder = rent_funder(&mut accounts, payer)?; // the owner , or a relayer paying for an owner with no SOL
 
-    // let check the pda seed is correct or not
-    let (pda, bump_seed) =
-        Pubkey::find_program_address(&[b"INIT", &ix_data.seed, payer.key.as_ref()], program_id);
-    if pda != *unsigned_account.key {
-        msg!("Error: Invalid seeds for PDA");
-        return Err(ProgramError::InvalidArgument);
-    }
+    check_not_paused(config.pause_listings, "new listings")?;
+    check_listing_policy(&config, ix_data.price, &ix_data.arbiter)?;
+    let pda = *unsigned_account.key;
 
     let rent = Rent::get()?;
     let required_lamports = rent.minimum_balance(std::mem::size_of::<InitAccountData>());
 
-    let ix = instruction::create_account(
-        payer.key,
+    let ix = system_instruction::create_account(
+        funder.key,
         &pda,
         required_lamports,
         std::mem::size_of::<InitAccountData>() as u64,
@@ -342,21 +199,26 @@ fn process_init(
     invoke_signed(
         &ix,
         &[
-            payer.clone(),
+            funder.clone(),
             unsigned_account.clone(),
             system_program.clone(),
         ],
         &[&[b"INIT", &ix_data.seed, payer.key.as_ref(), &[bump_seed]]],
     )?;
 
-    let mut binding = &mut unsigned_account.data.borrow_mut();
-    let unsigned_account_data = bytemuck::from_bytes_mut::<InitAccountData>(&mut binding);
+    let mut binding = unsigned_account.data.borrow_mut();
+    let unsigned_account_data = state::init::<InitAccountData>(&mut binding)?;
 
+    // description stay zeroed here , it is written with WRITE_DESCRIPTION and locked with PUBLISH
     unsigned_account_data.item_id = ix_data.item_id;
     unsigned_account_data.title = ix_data.title;
-    unsigned_account_data.description = ix_data.description;
     unsigned_account_data.price = ix_data.price;
     unsigned_account_data.payer = payer.key.to_bytes();
+    unsigned_account_data.rent_payer = funder.key.to_bytes();
+    unsigned_account_data.status = ListingStatus::Draft as u8;
+    unsigned_account_data.arbiter = ix_data.arbiter;
+    unsigned_account_data.payment_mint = ix_data.payment_mint;
+    unsigned_account_data.bump = bump_seed;
 
     msg!("Account initialized successfully");
 
@@ -365,79 +227,134 @@ fn process_init(
 
 fn update_account_data(
     program_id: &Pubkey,
-    accounts: &[AccountInfo], //[payer , unsign_account , system_program]
+    accounts: &[AccountInfo], //[payer , unsign_account , config]
     ix_data: &UpdateData,
 ) -> ProgramResult {
-    let account_info_iter = &mut accounts.iter();
-    let payer = next_account_info(account_info_iter)?; // the account that will pay for the rent
-    let pda_account = next_account_info(account_info_iter)?; // the account that will be created and store the data
+    let mut accounts = Accounts::new(program_id, accounts);
+    let payer = accounts.next_account()?.signer()?.info(); // the owner of the post
+    let pda_account = listing_of(accounts.next_account()?, &ix_data.seed, payer)?;
+    let config = load_config(accounts.next_account()?)?;
 
-    if !payer.is_signer {
-        msg!("Error: Payer account should be a signer");
-        return Err(ProgramError::InvalidArgument);
-    }
+    let mut binding = pda_account.data.borrow_mut();
+    let signed_account_data = load_mut::<InitAccountData>(&mut binding)?;
 
-    if pda_account.owner != program_id {
-        msg!("Error: Account not owned by this program");
-        return Err(ProgramError::MissingRequiredSignature);
-    }
-
-    // let check the pda seed is correct or not
-    let (pda, _bump_seed) =
-        Pubkey::find_program_address(&[b"INIT", &ix_data.seed, payer.key.as_ref()], program_id);
-    if pda != *pda_account.key {
-        msg!("Error: Invalid seeds for PDA");
-        return Err(ProgramError::InvalidArgument);
-    }
+    // no edit while a buyer has money in escrow for this price , and nothing to edit once it is sold
+    signed_account_data.check_editable()?;
 
-    let mut binding = &mut pda_account.data.borrow_mut();
-    let signed_account_data = bytemuck::from_bytes_mut::<InitAccountData>(&mut binding);
+    // a version 1 client know nothing of arbiters , its UPDATE keep the one the listing has
+    let arbiter = if ix_data.keep_arbiter != 0 {
+        signed_account_data.arbiter
+    } else {
+        ix_data.arbiter
+    };
+    check_listing_policy(&config, ix_data.price, &arbiter)?;
 
     signed_account_data.title = ix_data.title;
-    signed_account_data.description = ix_data.description;
     signed_account_data.price = ix_data.price;
+    signed_account_data.arbiter = arbiter;
+    signed_account_data.bump_revision()?;
+
+    // the description can't be changed in place once it is published , so the owner reopen the post ,
+    // write the new description in chunks and publish it again
+    if ix_data.reopen_description != 0 {
+        if signed_account_data.status()? == ListingStatus::Active {
+            signed_account_data.set_status(ListingStatus::Draft)?;
+        }
+        signed_account_data.description = [0; 1024];
+    }
 
     msg!("Account updated successfully");
     Ok(())
 }
 
-fn delete_account_data(
+fn write_description_chunk(
     program_id: &Pubkey,
-    accounts: &[AccountInfo], //[payer , unsign_account , system_program]
-    ix_data: &DeleteData,
+    accounts: &[AccountInfo], //[payer , pda_account]
+    ix_data: &DescriptionChunk,
+    chunk: &[u8],
 ) -> ProgramResult {
-    let account_info_iter = &mut accounts.iter();
-    let payer = next_account_info(account_info_iter)?; // the account that will pay for
-    let signed_account = next_account_info(account_info_iter)?; // the account that will be created and store the data
+    let mut accounts = Accounts::new(program_id, accounts);
+    let payer = accounts.next_account()?.signer()?.info(); // the owner of the post
+    let pda_account = listing_of(accounts.next_account()?, &ix_data.seed, payer)?; // the post account
 
-    if !payer.is_signer {
-        msg!("Error: Payer account should be a signer");
-        return Err(ProgramError::InvalidArgument);
+    if chunk.len() > DESCRIPTION_CHUNK_MAX {
+        msg!("Error: Description chunk is too big");
+        return Err(MarketplaceError::ChunkTooLarge.into());
     }
 
-    if signed_account.owner != program_id {
-        msg!("Error: Account not owned by this program");
-        return Err(ProgramError::MissingRequiredSignature);
+    let mut binding = pda_account.data.borrow_mut();
+    let post_data = load_mut::<InitAccountData>(&mut binding)?;
+
+    if post_data.status()? != ListingStatus::Draft {
+        msg!("Error: Post is published, the description is locked");
+        return Err(MarketplaceError::ListingContentLocked.into());
     }
 
-    // check is the payer is the real payer of this account or not
+    let start = ix_data.offset as usize;
+    let end = start
+        .checked_add(chunk.len())
+        .ok_or(MarketplaceError::ChunkOutOfBounds)?;
+    if end > post_data.description.len() {
+        msg!("Error: Description chunk is out of bounds");
+        return Err(MarketplaceError::ChunkOutOfBounds.into());
+    }
 
-    let (pda, _) =
-        Pubkey::find_program_address(&[b"INIT", &ix_data.seed, payer.key.as_ref()], program_id);
+    post_data.description[start..end].copy_from_slice(chunk);
+    post_data.bump_revision()?;
 
-    if signed_account.key != &pda {
-        msg!("Error: Invalid seeds or payer is not the real payer of this account");
-        return Err(ProgramError::InvalidArgument);
+    msg!("Description chunk written at offset {}", start);
+    Ok(())
+}
+
+fn publish_post(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo], //[payer , pda_account , config]
+    ix_data: &PublishData,
+) -> ProgramResult {
+    let mut accounts = Accounts::new(program_id, accounts);
+    let payer = accounts.next_account()?.signer()?.info(); // the owner of the post
+    let pda_account = listing_of(accounts.next_account()?, &ix_data.seed, payer)?; // the post account
+    let config = load_config(accounts.next_account()?)?;
+
+    // a draft only become buyable here , so the listing switch stop PUBLISH too and not only INIT
+    check_not_paused(config.pause_listings, "new listings")?;
+
+    let mut binding = pda_account.data.borrow_mut();
+    let post_data = load_mut::<InitAccountData>(&mut binding)?;
+
+    if post_data.status()? != ListingStatus::Draft {
+        msg!("Error: Post is already published");
+        return Err(MarketplaceError::ListingAlreadyPublished.into());
     }
 
-    **payer.try_borrow_mut_lamports()? = payer
-        .lamports()
-        .checked_add(signed_account.lamports())
-        .ok_or(ProgramError::InvalidArgument)?;
-    **signed_account.try_borrow_mut_lamports()? = 0;
+    post_data.set_status(ListingStatus::Active)?;
 
-    let mut data = signed_account.try_borrow_mut_data()?;
-    data.fill(0);
+    msg!("Post published successfully");
+    Ok(())
+}
+
+fn delete_account_data(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo], //[payer , unsign_account] + [rent_funder] if a relayer funded the listing
+    ix_data: &DeleteData,
+) -> ProgramResult {
+    let mut accounts = Accounts::new(program_id, accounts);
+    let payer = accounts.next_account()?.signer()?.writable()?.info(); // the owner
+    // the seeds hold the payer key , so only the real payer of this listing can match them
+    let signed_account = listing_of(accounts.next_account()?, &ix_data.seed, payer)?;
+
+    // make sure it really is a listing before we close it , a listing with a pending buy can't go away
+    // because the buyer money in escrow is tied to it
+    let rent_payer = {
+        let mut binding = signed_account.data.borrow_mut();
+        let listing = load_mut::<InitAccountData>(&mut binding)?;
+        listing.set_status(ListingStatus::Withdrawn)?;
+        listing.rent_payer
+    };
+
+    // the rent goes back to whoever funded it
+    let rent_receiver = rent_receiver(&mut accounts, &rent_payer, payer)?;
+    state::close(signed_account, rent_receiver)?;
 
     Ok(())
 }
@@ -446,430 +363,1544 @@ fn delete_account_data(
 
 fn buy_item(
     program_id: &Pubkey,
-    accounts: &[AccountInfo], //[payer , post_account , unsign_account , holder account, system_program]
+    accounts: &[AccountInfo], //[payer , post_account , deal_account , system_program , config] + for a token listing [mint , buyer_token_account , vault_token_account , token_program , associated_token_program] + optional [rent_funder]
     ix_data: &BuyInit,
 ) -> ProgramResult {
-    let account_info_iter = &mut accounts.iter();
-    let buyer = next_account_info(account_info_iter)?; // the account that will pay for
-    let post_account = next_account_info(account_info_iter)?; // the account that hold the post data
-    let unsigned_account = next_account_info(account_info_iter)?;
-    let holder_account = next_account_info(account_info_iter)?; // the account that will hold the money
-    let system_program = next_account_info(account_info_iter)?; // the system program account
-
-    if !buyer.is_signer {
-        msg!("Error: Buyer account should be a signer");
-        return Err(ProgramError::InvalidArgument);
+    let mut accounts = Accounts::new(program_id, accounts);
+    let buyer = accounts.next_account()?.signer()?.writable()?.info(); // the account that will pay for
+    // only INIT write a listing header into an account this program own , so owner + header check
+    // prove this is a real listing PDA and not some account the client made up
+    let post_account = accounts.next_account()?.writable()?.holds::<InitAccountData>()?.info();
+    // every buy get its own deal account , it hold the escrow too , so two buyers of the same item never share
+    // escrow and a CANCEL or SELL can only ever move the money of its own deal
+    let (deal_account, bump) = accounts.next_account()?.writable()?.find_pda(
+        &[b"DEAL", &ix_data.seed, buyer.key.as_ref()],
+        MarketplaceError::WrongBuyPda,
+    )?; // the deal account , created here
+    let deal_account = deal_account.info();
+    let system_program = accounts.next_account()?.system_program()?.info();
+    let config = load_config(accounts.next_account()?)?; // the marketplace fee is read from here
+
+    check_not_paused(config.pause_buys, "buys")?;
+
+    // the buyer recorded in the deal is always the signer , the client value is only a cross check
+    if ix_data.buyer != buyer.key.to_bytes() {
+        msg!("Error: Buyer in the instruction is not the signer");
+        return Err(MarketplaceError::NotBuyer.into());
     }
 
-    if unsigned_account.owner != program_id {
-        msg!("Error: Account not owned by this program");
-        return Err(ProgramError::MissingRequiredSignature);
-    }
-
-    let (pda, bump) =
-        Pubkey::find_program_address(&[b"BUY", &ix_data.seed, buyer.key.as_ref()], program_id);
-    if pda != *unsigned_account.key {
-        msg!("Error: Invalid seeds for PDA");
-        return Err(ProgramError::InvalidArgument);
-    }
+    // read only check before we create anything
+    let (listing_item_id, listing_price, listing_seller, listing_arbiter, listing_mint) = {
+        let post_binding = post_account.data.borrow();
+        let listing = load::<InitAccountData>(&post_binding)?;
 
-    let rent = Rent::get()?;
-    let required_lamports = rent.minimum_balance(std::mem::size_of::<BuyData>());
-
-    let (hold_pda, _hold_bump) =
-        Pubkey::find_program_address(&[b"HOLDER", ix_data.item_id.as_ref()], program_id);
-
-    if hold_pda != *holder_account.key {
-        msg!("Error: Invalid holder account PDA");
-        return Err(ProgramError::InvalidArgument);
+        // only an Active post can be bought , a draft is still uploading and a reserved one already has a buyer
+        if listing.status()? != ListingStatus::Active {
+            msg!("Error: Post is not open for buying");
+            return Err(MarketplaceError::ListingNotActive.into());
+        }
+        // the buyer agreed to this listing at this price , anything else fail instead of taking more money.
+        // only a version 1 BUY , that had no revision to send , skip the check
+        if ix_data.any_revision == 0 && listing.revision != ix_data.expected_revision {
+            msg!("Error: Listing changed since the buyer read it");
+            return Err(MarketplaceError::ListingRevisionMismatch.into());
+        }
+        if listing.price > ix_data.max_price {
+            msg!("Error: Listing price is above the buyer max price");
+            return Err(MarketplaceError::PriceAboveMax.into());
+        }
+        (
+            listing.item_id,
+            listing.price,
+            listing.payer,
+            listing.arbiter,
+            listing.payment_mint,
+        )
+    };
+
+    if ix_data.item_id != listing_item_id {
+        msg!("Error: Item id does not match the listing");
+        return Err(MarketplaceError::ItemIdMismatch.into());
     }
 
-    if holder_account.owner != program_id {
-        msg!("Error: Holder account not owned by this program");
-        return Err(ProgramError::MissingRequiredSignature);
+    // the policy is locked into the deal below , a token deal must be one the buyer can settle alone
+    let rent_policy = RegistryRentPolicy::from_u8(config.registry_rent_policy)?;
+    if listing_mint != [0; 32] && rent_policy.charges_seller_on_sell() {
+        msg!("Error: Registry rent policy {:?} charges the seller , not on a token deal", rent_policy);
+        return Err(MarketplaceError::RentPolicyNotForTokens.into());
     }
 
-    let ix = instruction::create_account(
-        buyer.key,
+    let pda = *deal_account.key;
+
+    // the token accounts come before the optional rent funder , so they are read before anything is created
+    let token_accounts = if listing_mint == [0; 32] {
+        None
+    } else {
+        let mint = accounts
+            .next_account()?
+            .is_key(&Pubkey::new_from_array(listing_mint), MarketplaceError::WrongMint)?
+            .info();
+        let mint_info = token::read_mint(mint)?;
+        let buyer_token_account = accounts.next_account()?.writable()?.info();
+        let vault = accounts.next_account()?.writable()?; // the ATA of the deal PDA , created here if missing
+        let token_program = accounts
+            .next_account()?
+            .is_key(mint.owner, MarketplaceError::WrongTokenProgram)?
+            .info();
+        let vault = vault
+            .is_key(
+                &token::get_associated_token_address(&pda, mint.key, token_program.key),
+                MarketplaceError::WrongTokenVault,
+            )?
+            .info();
+        let associated_token_program = accounts
+            .next_account()?
+            .is_key(&token::ASSOCIATED_TOKEN_PROGRAM_ID, MarketplaceError::WrongTokenProgram)?
+            .info();
+        check_token_account(buyer_token_account, token_program.key, mint.key, buyer.key)?;
+        Some((mint, mint_info, buyer_token_account, vault, token_program, associated_token_program))
+    };
+    // the deal rent (and the vault rent of a token deal) , the buyer or a relayer. it get both back on close
+    let funder = rent_funder(&mut accounts, buyer)?;
+
+    let size = std::mem::size_of::<DealData>();
+    let ix = system_instruction::create_account(
+        funder.key,
         &pda,
-        required_lamports,
-        std::mem::size_of::<BuyData>() as u64,
+        Rent::get()?.minimum_balance(size),
+        size as u64,
         program_id,
     );
 
     invoke_signed(
         &ix,
         &[
-            buyer.clone(),
-            unsigned_account.clone(),
+            funder.clone(),
+            deal_account.clone(),
             system_program.clone(),
         ],
-        &[&[b"BUY", &ix_data.seed, buyer.key.as_ref(), &[bump]]],
+        &[&[b"DEAL", &ix_data.seed, buyer.key.as_ref(), &[bump]]],
     )?;
-    let mut seller_data = &mut post_account.data.borrow_mut();
-    let post_data = bytemuck::from_bytes_mut::<InitAccountData>(&mut seller_data);
+    let deal_rent = deal_account.lamports();
+
+    // the listing is borrowed mutably only for the status change
+    load_mut::<InitAccountData>(&mut post_account.data.borrow_mut())?
+        .set_status(ListingStatus::Reserved)?;
+
+    let mut vault_token_account = [0; 32];
+    let escrowed = match token_accounts {
+        None => {
+            let transfer_ix = system_instruction::transfer(buyer.key, deal_account.key, listing_price);
+
+            invoke(
+                &transfer_ix,
+                &[
+                    buyer.clone(),
+                    deal_account.clone(),
+                    system_program.clone(),
+                ],
+            )?;
+
+            // the deal must hold exactly its rent reserve plus the escrowed amount , nothing more nothing less
+            let expected = deal_rent
+                .checked_add(listing_price)
+                .ok_or(MarketplaceError::ArithmeticOverflow)?;
+            if deal_account.lamports() != expected {
+                msg!("Error: Deal balance does not match the deposit");
+                return Err(MarketplaceError::LamportImbalance.into());
+            }
+            listing_price
+        }
+        Some((mint, mint_info, buyer_token_account, vault, token_program, associated_token_program)) => {
+            invoke(
+                &token::create_associated_token_account_idempotent(
+                    funder.key,
+                    &pda,
+                    mint.key,
+                    token_program.key,
+                ),
+                &[
+                    funder.clone(),
+                    vault.clone(),
+                    deal_account.clone(),
+                    mint.clone(),
+                    system_program.clone(),
+                    token_program.clone(),
+                    associated_token_program.clone(),
+                ],
+            )?;
+
+            let before = token::read_token_account(vault, token_program.key)?.amount;
+            invoke(
+                &token::transfer_checked(
+                    token_program.key,
+                    buyer_token_account.key,
+                    mint.key,
+                    vault.key,
+                    buyer.key,
+                    listing_price,
+                    mint_info.decimals,
+                ),
+                &[
+                    buyer_token_account.clone(),
+                    mint.clone(),
+                    vault.clone(),
+                    buyer.clone(),
+                    token_program.clone(),
+                ],
+            )?;
+            let after = token::read_token_account(vault, token_program.key)?.amount;
+
+            // a Token-2022 transfer fee is kept back from the receiver , the escrow hold what really arrived
+            vault_token_account = vault.key.to_bytes();
+            after
+                .checked_sub(before)
+                .ok_or(MarketplaceError::ArithmeticOverflow)?
+        }
+    };
+
+    // the deal bind itself to its listing and record what it hold and for whom , SELL and CANCEL check every
+    // account against these keys and pay out from amount and not from the raw balance
+    let mut binding = deal_account.data.borrow_mut();
+    let deal = state::init::<DealData>(&mut binding)?;
+    deal.item_id = listing_item_id;
+    deal.listing = post_account.key.to_bytes();
+    deal.buyer = buyer.key.to_bytes();
+    deal.seller = listing_seller;
+    deal.price = listing_price;
+    deal.arbiter = listing_arbiter;
+    deal.bump = bump;
+    deal.seed = ix_data.seed;
+    // the fee is locked like the price , a later config change don't touch a running deal
+    deal.fee_bps = config.fee_bps;
+    deal.registry_rent_policy = config.registry_rent_policy;
+    deal.confirm_window = config.confirm_window;
+
+    // the seller has config.accept_window to ship , after that the buyer money can be taken back with EXPIRE
+    let now = Clock::get()?.unix_timestamp;
+    deal.created_at = now;
+    deal.accept_deadline = now
+        .checked_add(config.accept_window)
+        .ok_or(MarketplaceError::ArithmeticOverflow)?;
+
+    deal.amount = escrowed;
+    deal.rent_payer = funder.key.to_bytes();
+    deal.mint = listing_mint;
+    deal.token_account = vault_token_account;
+
+    msg!("Deal account initialized successfully");
+    Ok(())
+}
 
-    let mut binding = &mut unsigned_account.data.borrow_mut();
-    let buy_account_data = bytemuck::from_bytes_mut::<BuyData>(&mut binding);
+fn cancel_buy(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo], //[buyer , deal_account , post_account] + for a token deal [mint , vault_token_account , token_program , buyer_token_account] + [deal_rent_payer] if not the buyer
+) -> ProgramResult {
+    let mut accounts = Accounts::new(program_id, accounts);
+    let buyer = accounts.next_account()?.signer()?.writable()?.info();
+    let deal_account = accounts.next_account()?.writable()?.holds::<DealData>()?.info();
+    let post_account = accounts.next_account()?.writable()?.holds::<InitAccountData>()?.info(); // the listing that go back to Active
 
-    buy_account_data.item_id = ix_data.item_id;
-    buy_account_data.buyer = ix_data.buyer;
-    buy_account_data.price = post_data.price;
-    buy_account_data.seller = post_data.payer;
+    let deal = load_deal(deal_account, post_account)?;
 
-    let transfer_ix = instruction::transfer(buyer.key, holder_account.key, post_data.price);
+    if deal.buyer != buyer.key.to_bytes() {
+        msg!("Error: Buyer account mismatch");
+        return Err(MarketplaceError::NotBuyer.into());
+    }
 
-    invoke(
-        &transfer_ix,
-        &[
-            buyer.clone(),
-            holder_account.clone(),
-            system_program.clone(),
-        ],
-    )?;
+    // the seller already handed the item over or the arbiter has it , the buyer can't take the money back now
+    deal.check_status(DealStatus::Pending)?;
+
+    let token = load_token_escrow(&mut accounts, &deal)?;
+    let refund = next_payout(&mut accounts, token.as_ref(), buyer, deal.amount)?;
+    let rent_receiver = rent_receiver(&mut accounts, &deal.rent_payer, buyer)?;
+    refund_deal(deal_account, post_account, &deal, token.as_ref(), &[refund], buyer, rent_receiver)?;
+    msg!("Buy cancelled successfully");
 
-    msg!("Buy account initialized successfully");
     Ok(())
 }
 
-fn cancel_buy(
+fn expire_buy(
     program_id: &Pubkey,
-    accounts: &[AccountInfo], //[buyer ,Buy account ,holder_account ]
-    ix_data: &CancelBuy,
+    accounts: &[AccountInfo], //[caller , buyer , deal_account , post_account] + for a token deal [mint , vault_token_account , token_program , buyer_token_account] + [deal_rent_payer] if not the buyer
 ) -> ProgramResult {
-    let account_info_iter = &mut accounts.iter();
-    let buyer = next_account_info(account_info_iter)?;
-    let buy_account = next_account_info(account_info_iter)?;
-    let holder_account = next_account_info(account_info_iter)?;
+    let mut accounts = Accounts::new(program_id, accounts);
+    let caller = accounts.next_account()?.signer()?.writable()?.info(); // the buyer or any crank , get the bounty if not the buyer
+    let buyer = accounts.next_shared()?.writable()?.info(); // may be the caller itself
+    let deal_account = accounts.next_account()?.writable()?.holds::<DealData>()?.info();
+    let post_account = accounts.next_account()?.writable()?.holds::<InitAccountData>()?.info();
+
+    let deal = load_deal(deal_account, post_account)?;
+
+    if deal.buyer != buyer.key.to_bytes() {
+        msg!("Error: Buyer account mismatch");
+        return Err(MarketplaceError::NotBuyer.into());
+    }
+
+    // a shipped deal is settled by SELL or CLAIM and a disputed one by the arbiter , only a deal the seller
+    // never picked up expire
+    deal.check_status(DealStatus::Pending)?;
 
-    if !buyer.is_signer {
-        msg!("Error: Buyer account should be a signer");
-        return Err(ProgramError::InvalidArgument);
+    if Clock::get()?.unix_timestamp < deal.accept_deadline {
+        msg!("Error: Seller can still ship until {}", deal.accept_deadline);
+        return Err(MarketplaceError::AcceptWindowOpen.into());
     }
 
-    let binding = buy_account.data.borrow();
-    let buy_data = bytemuck::try_from_bytes::<BuyData>(&binding)
-        .or_else(|_| Err(ProgramError::InvalidAccountData))?;
+    let token = load_token_escrow(&mut accounts, &deal)?;
+
+    // a crank is paid out of the escrow , the buyer cranking its own deal just get everything back.
+    // a token escrow can't pay a lamport bounty , and the deal rent belong to its funder , so no bounty there
+    let crank = caller.key != buyer.key;
+    let bounty = if crank && token.is_none() {
+        state::expire_crank_bounty(deal.amount)
+    } else {
+        0
+    };
+    let mut payouts = vec![next_payout(
+        &mut accounts,
+        token.as_ref(),
+        buyer,
+        deal.amount - bounty,
+    )?];
+    let rent_receiver = rent_receiver(&mut accounts, &deal.rent_payer, buyer)?;
+    if bounty > 0 {
+        payouts.push(Payout {
+            wallet: caller,
+            token_account: None,
+            amount: bounty,
+        });
+        msg!("Crank bounty: {}", bounty);
+    }
+
+    refund_deal(deal_account, post_account, &deal, token.as_ref(), &payouts, buyer, rent_receiver)?;
+    msg!("Buy expired and refunded");
+
+    Ok(())
+}
+
+fn reject_buy(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo], //[seller , buyer , deal_account , post_account] + for a token deal [mint , vault_token_account , token_program , buyer_token_account] + [deal_rent_payer] if not the buyer
+    ix_data: &RejectData,
+) -> ProgramResult {
+    let mut accounts = Accounts::new(program_id, accounts);
+    let seller = accounts.next_account()?.signer()?.info();
+    let buyer = accounts.next_account()?.writable()?.info(); // get the refund and the rent back , don't need to sign
+    let deal_account = accounts.next_account()?.writable()?.holds::<DealData>()?.info();
+    let post_account = accounts.next_account()?.writable()?.holds::<InitAccountData>()?.info();
+
+    let deal = load_deal(deal_account, post_account)?;
 
-    if buy_data.buyer != buyer.key.to_bytes() {
+    if deal.seller != seller.key.to_bytes() {
+        msg!("Error: Seller account mismatch");
+        return Err(MarketplaceError::NotSeller.into());
+    }
+
+    if deal.buyer != buyer.key.to_bytes() {
         msg!("Error: Buyer account mismatch");
-        return Err(ProgramError::InvalidArgument);
+        return Err(MarketplaceError::NotBuyer.into());
+    }
+
+    // giving the money back only ever hurt the seller , so the seller can reject a Pending or Shipped deal ,
+    // a disputed one is frozen for the arbiter
+    if deal.status()? == DealStatus::Disputed {
+        msg!("Error: Deal is in dispute");
+        return Err(MarketplaceError::DealDisputed.into());
     }
 
-    let (hold_pda, _hold_bump) =
-        Pubkey::find_program_address(&[b"HOLDER", ix_data.item_id.as_ref()], program_id);
+    let token = load_token_escrow(&mut accounts, &deal)?;
+    let refund = next_payout(&mut accounts, token.as_ref(), buyer, deal.amount)?;
+    let rent_receiver = rent_receiver(&mut accounts, &deal.rent_payer, buyer)?;
+    refund_deal(deal_account, post_account, &deal, token.as_ref(), &[refund], buyer, rent_receiver)?;
+
+    msg!(
+        "Event: BuyRejected deal={} buyer={} reason={}",
+        deal_account.key,
+        buyer.key,
+        ix_data.reason
+    );
+    Ok(())
+}
+
+fn open_dispute(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo], //[party , deal_account]
+    ix_data: &EvidenceData,
+) -> ProgramResult {
+    let mut accounts = Accounts::new(program_id, accounts);
+    let party = accounts.next_account()?.signer()?.info(); // the buyer or the seller of the deal
+    let deal_account = accounts.next_account()?.writable()?.holds::<DealData>()?.info();
+
+    let mut binding = deal_account.data.borrow_mut();
+    let deal = load_mut::<DealData>(&mut binding)?;
 
-    if hold_pda != *holder_account.key {
-        msg!("Error: Invalid holder account PDA");
-        return Err(ProgramError::InvalidArgument);
+    if deal.arbiter == [0; 32] {
+        msg!("Error: Listing had no arbiter when this deal was made");
+        return Err(MarketplaceError::NoArbiter.into());
     }
 
-    if holder_account.owner != program_id {
-        msg!("Error: Holder account not owned by this program");
-        return Err(ProgramError::MissingRequiredSignature);
+    match deal.status()? {
+        DealStatus::Pending | DealStatus::Shipped => {}
+        DealStatus::Disputed => {
+            msg!("Error: Deal is already in dispute");
+            return Err(MarketplaceError::DealDisputed.into());
+        }
     }
 
-    **buyer.try_borrow_mut_lamports()? = buyer
-        .lamports()
-        .checked_add(holder_account.lamports())
-        .ok_or(ProgramError::InvalidArgument)?;
-    **holder_account.try_borrow_mut_lamports()? = 0;
+    record_evidence(deal, party, ix_data)?;
 
-    **buyer.try_borrow_mut_lamports()? = buyer
-        .lamports()
-        .checked_add(buy_account.lamports())
-        .ok_or(ProgramError::InvalidArgument)?;
-    **buy_account.try_borrow_mut_lamports()? = 0;
+    // from here CANCEL , SHIP , SELL , CLAIM , EXPIRE and REJECT all refuse the deal , only the arbiter move the escrow
+    deal.status = DealStatus::Disputed as u8;
+    deal.disputed_at = Clock::get()?.unix_timestamp;
 
-    let mut data = buy_account.try_borrow_mut_data()?;
-    data.fill(0);
-    msg!("Buy cancelled successfully");
+    msg!("Event: DisputeOpened deal={} by={}", deal_account.key, party.key);
+    Ok(())
+}
+
+fn submit_evidence(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo], //[party , deal_account]
+    ix_data: &EvidenceData,
+) -> ProgramResult {
+    let mut accounts = Accounts::new(program_id, accounts);
+    let party = accounts.next_account()?.signer()?.info();
+    let deal_account = accounts.next_account()?.writable()?.holds::<DealData>()?.info();
+
+    let mut binding = deal_account.data.borrow_mut();
+    let deal = load_mut::<DealData>(&mut binding)?;
 
+    if deal.status()? != DealStatus::Disputed {
+        msg!("Error: Deal is not in dispute");
+        return Err(MarketplaceError::DealNotDisputed.into());
+    }
+
+    record_evidence(deal, party, ix_data)?;
+
+    msg!("Event: EvidenceSubmitted deal={} by={}", deal_account.key, party.key);
     Ok(())
 }
 
-fn sell_item(
+// the evidence slot of the party , the latest hash replace the previous one (the events keep the history)
+fn record_evidence(
+    deal: &mut DealData,
+    party: &AccountInfo,
+    ix_data: &EvidenceData,
+) -> Result<(), MarketplaceError> {
+    let party_key = party.key.to_bytes();
+    let slot = if party_key == deal.buyer {
+        &mut deal.buyer_evidence
+    } else if party_key == deal.seller {
+        &mut deal.seller_evidence
+    } else {
+        msg!("Error: Signer is not a party of this deal");
+        return Err(MarketplaceError::NotDealParty);
+    };
+    if ix_data.evidence != [0; 32] {
+        *slot = ix_data.evidence;
+    }
+    Ok(())
+}
+
+fn resolve_dispute(
     program_id: &Pubkey,
-    accounts: &[AccountInfo], //[seller_account,buyer_account, buy_account, post_account , holder_account , system_program, imutiable_registry_accont, temp_account] we can you lookup table here to reduce the transaction fee.
-    ix_data: &SellData,
+    accounts: &[AccountInfo], //[arbiter , seller , buyer , deal_account , post_account , dispute_record , system_program , config] + for a token deal [mint , vault_token_account , token_program , seller_token_account , buyer_token_account] + [deal_rent_payer] if not the buyer
+    ix_data: &ResolveData,
 ) -> ProgramResult {
-    let account_info_iter = &mut accounts.iter();
-    let seller = next_account_info(account_info_iter)?;
-    let buyer = next_account_info(account_info_iter)?;
-    let buy_account = next_account_info(account_info_iter)?;
-    let post_account = next_account_info(account_info_iter)?;
-    let holder_account = next_account_info(account_info_iter)?;
-    let system_program = next_account_info(account_info_iter)?;
-    let imutiable_registry_account = next_account_info(account_info_iter)?;
-    let temp_account = next_account_info(account_info_iter)?;
-
-    if !seller.is_signer && !buyer.is_signer {
-        msg!("Error: Seller account should be a signer");
-        return Err(ProgramError::InvalidArgument);
-    }
-
-    let (pda_post, _bump_post) = Pubkey::find_program_address(
-        &[b"INIT", &ix_data.seed_post, seller.key.as_ref()],
+    let mut accounts = Accounts::new(program_id, accounts);
+    let arbiter = accounts.next_account()?.signer()?.writable()?.info(); // sign the ruling and pay the record rent
+    let seller = accounts.next_account()?.writable()?.info();
+    let buyer = accounts.next_account()?.writable()?.info();
+    let deal_account = accounts.next_account()?.writable()?.holds::<DealData>()?.info();
+    let post_account = accounts.next_account()?.writable()?.holds::<InitAccountData>()?.info();
+    let (record_account, record_bump) = accounts.next_account()?.writable()?.find_pda(
+        &[b"DISPUTE", deal_account.key.as_ref()],
+        MarketplaceError::WrongDisputePda,
+    )?; // the outcome , created here
+    let record_account = record_account.info();
+    let system_program = accounts.next_account()?.system_program()?.info();
+    let config = load_config(accounts.next_account()?)?;
+
+    let deal = load_deal(deal_account, post_account)?;
+
+    if deal.arbiter != arbiter.key.to_bytes() {
+        msg!("Error: Arbiter account mismatch");
+        return Err(MarketplaceError::NotArbiter.into());
+    }
+
+    if deal.status()? != DealStatus::Disputed {
+        msg!("Error: Deal is not in dispute");
+        return Err(MarketplaceError::DealNotDisputed.into());
+    }
+
+    if deal.buyer != buyer.key.to_bytes() {
+        msg!("Error: Buyer account mismatch");
+        return Err(MarketplaceError::NotBuyer.into());
+    }
+
+    if deal.seller != seller.key.to_bytes() {
+        msg!("Error: Seller account mismatch");
+        return Err(MarketplaceError::NotSeller.into());
+    }
+
+    if ix_data.seller_bps > 10_000 {
+        msg!("Error: Split of {} basis points", ix_data.seller_bps);
+        return Err(MarketplaceError::InvalidSplit.into());
+    }
+
+    // the seller share round down , the lamport left by the rounding goes to the buyer
+    let seller_amount = (deal.amount as u128 * ix_data.seller_bps as u128 / 10_000) as u64;
+    let buyer_amount = deal
+        .amount
+        .checked_sub(seller_amount)
+        .ok_or(MarketplaceError::ArithmeticOverflow)?;
+
+    // refunds are never paused , only a ruling that pay the seller count as a settlement
+    if seller_amount > 0 {
+        check_not_paused(config.pause_settlements, "settlements")?;
+    }
+
+    let token = load_token_escrow(&mut accounts, &deal)?;
+    let seller_share = next_payout(&mut accounts, token.as_ref(), seller, seller_amount)?;
+    let buyer_share = next_payout(&mut accounts, token.as_ref(), buyer, buyer_amount)?;
+    let rent_receiver = rent_receiver(&mut accounts, &deal.rent_payer, buyer)?;
+
+    // the record address is known from the deal , lamports sent to it before must not block the ruling
+    create_pda(
         program_id,
-    );
-    let (pda_buy, _bump_buy) =
-        Pubkey::find_program_address(&[b"BUY", &ix_data.seed_buy, buyer.key.as_ref()], program_id);
+        arbiter,
+        record_account,
+        std::mem::size_of::<DisputeRecordData>(),
+        &[b"DISPUTE", deal_account.key.as_ref(), &[record_bump]],
+        system_program,
+    )?;
 
-    if pda_post != *post_account.key {
-        msg!("Error: Invalid post account PDA");
-        return Err(ProgramError::InvalidArgument);
+    {
+        let mut binding = record_account.data.borrow_mut();
+        let record = state::init::<DisputeRecordData>(&mut binding)?;
+        record.item_id = deal.item_id;
+        record.deal = deal_account.key.to_bytes();
+        record.buyer = deal.buyer;
+        record.seller = deal.seller;
+        record.arbiter = deal.arbiter;
+        record.buyer_evidence = deal.buyer_evidence;
+        record.seller_evidence = deal.seller_evidence;
+        record.amount = deal.amount;
+        record.seller_amount = seller_amount;
+        record.buyer_amount = buyer_amount;
+        record.seller_bps = ix_data.seller_bps;
+        record.timestamp = Clock::get()?.unix_timestamp as u64;
     }
 
-    if post_account.owner != program_id {
-        msg!("Error: Post account not owned by this program");
-        return Err(ProgramError::MissingRequiredSignature);
+    // a full refund put the listing back on sale , any payout to the seller count as a sale
+    let next = if seller_amount == 0 {
+        ListingStatus::Active
+    } else {
+        ListingStatus::Sold
+    };
+    load_mut::<InitAccountData>(&mut post_account.data.borrow_mut())?.set_status(next)?;
+
+    // both shares , then the deal rent back to whoever funded it
+    settle_escrow(
+        deal_account,
+        &deal,
+        token.as_ref(),
+        &[seller_share, buyer_share],
+        rent_receiver,
+    )?;
+
+    msg!(
+        "Event: DisputeResolved deal={} seller_amount={} buyer_amount={}",
+        deal_account.key,
+        seller_amount,
+        buyer_amount
+    );
+    Ok(())
+}
+
+// the end of a deal that did not go through : the listing goes back to Active , the escrow goes back to the
+// buyer , the deal rent back to whoever funded it (the buyer or its relayer) , and the deal account is closed
+fn refund_deal<'a>(
+    deal_account: &AccountInfo<'a>,
+    post_account: &AccountInfo<'a>,
+    deal: &DealData,
+    token: Option<&TokenEscrow<'a, '_>>,
+    payouts: &[Payout<'a, '_>],
+    buyer: &AccountInfo<'a>,
+    rent_receiver: &AccountInfo<'a>,
+) -> ProgramResult {
+    // the listing this deal reserved is open for other buyers again
+    load_mut::<InitAccountData>(&mut post_account.data.borrow_mut())?
+        .set_status(ListingStatus::Active)?;
+
+    // refund : the escrowed amount go back to the buyer who deposited it
+    if deal.buyer != buyer.key.to_bytes() {
+        msg!("Error: Buyer is not the depositor of this escrow");
+        return Err(MarketplaceError::EscrowPayeeMismatch.into());
     }
+    settle_escrow(deal_account, deal, token, payouts, rent_receiver)
+}
 
-    if pda_buy != *buy_account.key {
-        msg!("Error: Invalid buy account PDA");
-        return Err(ProgramError::InvalidArgument);
+fn ship_item(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo], //[seller , deal_account]
+) -> ProgramResult {
+    let mut accounts = Accounts::new(program_id, accounts);
+    let seller = accounts.next_account()?.signer()?.info();
+    let deal_account = accounts.next_account()?.writable()?.holds::<DealData>()?.info();
+
+    let mut binding = deal_account.data.borrow_mut();
+    let deal = load_mut::<DealData>(&mut binding)?;
+
+    if deal.seller != seller.key.to_bytes() {
+        msg!("Error: Seller account mismatch");
+        return Err(MarketplaceError::NotSeller.into());
     }
 
-    if post_account.owner != program_id {
-        msg!("Error: Buy account not owned by this program");
-        return Err(ProgramError::MissingRequiredSignature);
+    deal.check_status(DealStatus::Pending)?;
+
+    // too late , the deal can only be refunded now
+    let now = Clock::get()?.unix_timestamp;
+    if now >= deal.accept_deadline {
+        msg!("Error: Accept deadline {} is over", deal.accept_deadline);
+        return Err(MarketplaceError::AcceptDeadlinePassed.into());
     }
 
-    let binding = buy_account.data.borrow();
-    let data = bytemuck::from_bytes::<BuyInit>(&binding);
-    let (hold_pda, _hold_bump) =
-        Pubkey::find_program_address(&[b"HOLDER", data.item_id.as_ref()], program_id);
+    // the buyer confirm window start now , its length was locked at BUY and the deadline is stored on the deal
+    // so a later SET_CONFIG never move the deadline of a deal that is already running
+    deal.status = DealStatus::Shipped as u8;
+    deal.shipped_at = now;
+    deal.confirm_deadline = now
+        .checked_add(deal.confirm_window)
+        .ok_or(MarketplaceError::ArithmeticOverflow)?;
+
+    msg!("Deal marked shipped , buyer can confirm until {}", deal.confirm_deadline);
+    Ok(())
+}
 
-    if hold_pda != *holder_account.key {
-        msg!("Error: Invalid holder account PDA");
-        return Err(ProgramError::InvalidArgument);
+// SELL (timeout_claim = false) : the buyer confirm the receipt and the escrow goes to the seller.
+// CLAIM (timeout_claim = true) : the buyer never confirmed , the seller take the escrow after confirm_deadline.
+// both need the deal to be shipped first , the registry rent is paid as the rent policy locked at BUY say.
+fn sell_item(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo], //[seller_account,buyer_account, deal_account, post_account , system_program, imutiable_registry_accont , treasury , config] + for a token deal [mint , vault_token_account , token_program , seller_token_account , treasury_token_account , associated_token_program] + [deal_rent_payer] if not the buyer + optional [rent_funder]
+    timeout_claim: bool,
+) -> ProgramResult {
+    let mut accounts = Accounts::new(program_id, accounts);
+    let seller = accounts.next_account()?.writable()?;
+    let buyer = accounts.next_account()?.writable()?;
+    let settler = (if timeout_claim { seller } else { buyer }).signer()?.info();
+    let (seller, buyer) = (seller.info(), buyer.info());
+    let deal_account = accounts.next_account()?.writable()?.holds::<DealData>()?.info();
+    let post_account = accounts.next_account()?.writable()?.holds::<InitAccountData>()?.info();
+    let system_program = accounts.next_account()?.system_program()?.info();
+    // seeded with the item id , checked once the deal is loaded
+    let imutiable_registry_account = accounts.next_account()?.writable()?;
+    let treasury = accounts.next_account()?.writable()?; // take the marketplace fee
+    check_treasury(treasury)?;
+    let treasury = treasury.info();
+    let config = load_config(accounts.next_account()?)?;
+
+    check_not_paused(config.pause_settlements, "settlements")?;
+
+    let deal = load_deal(deal_account, post_account)?;
+
+    if deal.buyer != buyer.key.to_bytes() {
+        msg!("Error: Buyer account mismatch");
+        return Err(MarketplaceError::NotBuyer.into());
     }
 
-    if holder_account.owner != program_id {
-        msg!("Error: Holder account not owned by this program");
-        return Err(ProgramError::MissingRequiredSignature);
+    if deal.seller != seller.key.to_bytes() {
+        msg!("Error: Seller account mismatch");
+        return Err(MarketplaceError::NotSeller.into());
     }
 
-    let (temp_pda , _temp_bump) = Pubkey::find_program_address(&[b"TEMP", buyer.key.as_ref(), seller.key.as_ref(), data.item_id.as_ref()], program_id);
+    // nothing is released before the seller say the item is handed over , nor while the arbiter has it
+    deal.check_status(DealStatus::Shipped)?;
 
-    if temp_pda != *temp_account.key{
-         msg!("Error: Invalid temp account PDA");
-        return Err(ProgramError::InvalidArgument);
+    if timeout_claim && Clock::get()?.unix_timestamp < deal.confirm_deadline {
+        msg!("Error: Buyer can still confirm until {}", deal.confirm_deadline);
+        return Err(MarketplaceError::ConfirmWindowOpen.into());
     }
 
-    let (pda_imu, _bump_imu) = Pubkey::find_program_address(
+    // the fee round down , the seller get the rest
+    let fee = (deal.amount as u128 * deal.fee_bps as u128 / MAX_FEE_BPS as u128) as u64;
+    let seller_amount = deal
+        .amount
+        .checked_sub(fee)
+        .ok_or(MarketplaceError::ArithmeticOverflow)?;
+
+    let token = load_token_escrow(&mut accounts, &deal)?;
+    let mut seller_share = next_payout(&mut accounts, token.as_ref(), seller, seller_amount)?;
+    let treasury_token_accounts = match token.as_ref() {
+        None => None,
+        Some(_) => {
+            let account = accounts.next_account()?.writable()?.info();
+            let associated_token_program = accounts
+                .next_account()?
+                .is_key(&token::ASSOCIATED_TOKEN_PROGRAM_ID, MarketplaceError::WrongTokenProgram)?
+                .info();
+            Some((account, associated_token_program))
+        }
+    };
+    let rent_receiver = rent_receiver(&mut accounts, &deal.rent_payer, buyer)?; // get the deal rent back
+    // a relayer may pay the rent of what SELL create , the registry and the treasury ATA , for the settler.
+    // it only ever pay , so any signer is taken on purpose , also the relayer that funded the deal and was
+    // just passed as the rent receiver
+    let funder = match accounts.next_optional_shared()? {
+        Some(funder) => Some(funder.signer()?.writable()?.info()),
+        None => None,
+    };
+
+    let treasury_token_account = match (token.as_ref(), treasury_token_accounts) {
+        (Some(token), Some((account, associated_token_program))) => {
+            // the treasury may never have been paid in this mint , the settler open its ATA
+            if fee > 0 {
+                let payer = funder.unwrap_or(settler);
+                invoke(
+                    &token::create_associated_token_account_idempotent(
+                        payer.key,
+                        treasury.key,
+                        token.mint.key,
+                        token.token_program.key,
+                    ),
+                    &[
+                        payer.clone(),
+                        account.clone(),
+                        treasury.clone(),
+                        token.mint.clone(),
+                        system_program.clone(),
+                        token.token_program.clone(),
+                        associated_token_program.clone(),
+                    ],
+                )?;
+                check_token_account(account, token.token_program.key, token.mint.key, treasury.key)?;
+            }
+            Some(account)
+        }
+        _ => None,
+    };
+    // the seller share last , tokens sent to the vault on top of the escrow go to the seller
+    let treasury_share = Payout {
+        wallet: treasury,
+        token_account: treasury_token_account,
+        amount: fee,
+    };
+
+    let (imutiable_registry_account, bump_imu) = imutiable_registry_account.find_pda(
         &[
             b"IMUTABLE",
-            data.item_id.as_ref(),
+            deal.item_id.as_ref(),
             buyer.key.as_ref(),
             seller.key.as_ref(),
         ],
-        program_id,
-    );
-    if pda_imu != *imutiable_registry_account.key {
-        msg!("Error: Invalid imutiable registry account PDA");
-        return Err(ProgramError::InvalidArgument);
-    }
+        MarketplaceError::WrongRegistryPda,
+    )?;
+    let imutiable_registry_account = imutiable_registry_account.info();
+    let pda_imu = *imutiable_registry_account.key;
 
     let rent = Rent::get()?;
     let required_lamports = rent.minimum_balance(std::mem::size_of::<ImmutableRegistryData>());
     let space = std::mem::size_of::<ImmutableRegistryData>() as u64;
-    let split_lamport = required_lamports / 2 + 1;
-
-    let ix_acccount_fee_seller = instruction::transfer(seller.key, temp_account.key, split_lamport);
-    let ix_account_fee_buyer = instruction::transfer(buyer.key, temp_account.key, split_lamport);
 
-    invoke(
-        &ix_acccount_fee_seller,
-        &[seller.clone(), buy_account.clone(),temp_account.clone(),system_program.clone()],
+    // the rent is paid straight into the registry address by whoever the policy locked at BUY name. we fund +
+    // allocate + assign instead of create_account , so lamports somebody sent to the address before can't make
+    // SELL fail. only what is missing for the rent is paid , so nothing is left over to give back
+    let missing = required_lamports.saturating_sub(imutiable_registry_account.lamports());
+    let shares = deal.rent_policy()?.shares(missing, timeout_claim);
+    let from_proceeds = pay_registry_rent(
+        imutiable_registry_account,
+        &shares,
+        [buyer, seller],
+        funder,
+        treasury,
+        system_program,
     )?;
-    invoke(&ix_account_fee_buyer, &[buyer.clone(), temp_account.clone(),system_program.clone()])?;
-
-    let ix = instruction::create_account(temp_account.key, &pda_imu, required_lamports, space, program_id);
+    let mut deal_left = deal;
+    if from_proceeds > 0 {
+        match token {
+            // the proceeds are tokens , so the seller they belong to pay the lamports and must sign for it ,
+            // unless a relayer signed to pay them. the settler is never charged for the seller. BUY refuse the
+            // policies that land here on SELL , so it is only a CLAIM or a deal bought before that check
+            Some(_) => {
+                let payer = match funder {
+                    Some(funder) => funder,
+                    None if seller.is_signer => seller,
+                    None => {
+                        msg!("Error: Seller must sign to pay {} lamports of registry rent", from_proceeds);
+                        return Err(MarketplaceError::RentPayerNotSigner.into());
+                    }
+                };
+                invoke(
+                    &system_instruction::transfer(payer.key, &pda_imu, from_proceeds),
+                    &[payer.clone(), imutiable_registry_account.clone(), system_program.clone()],
+                )?
+            }
+            None => {
+                seller_share.amount = seller_share.amount.checked_sub(from_proceeds).ok_or_else(|| {
+                    msg!("Error: Seller gets {} , the registry rent is {}", seller_share.amount, from_proceeds);
+                    MarketplaceError::RentAboveProceeds
+                })?;
+                take_from_escrow(deal_account, &mut deal_left, imutiable_registry_account, from_proceeds)?;
+            }
+        }
+        msg!("Registry rent: {} from the sale proceeds", from_proceeds);
+    }
 
+    let registry_seeds: &[&[u8]] = &[
+        b"IMUTABLE",
+        deal.item_id.as_ref(),
+        buyer.key.as_ref(),
+        seller.key.as_ref(),
+        &[bump_imu],
+    ];
     invoke_signed(
-        &ix,
-        &[temp_account.clone(),imutiable_registry_account.clone(),system_program.clone()],
-        &[&[
-            b"IMUTABLE",
-            data.item_id.as_ref(),
-            buyer.key.as_ref(),
-            seller.key.as_ref(),
-            &[_bump_imu],
-        ]],
+        &system_instruction::allocate(&pda_imu, space),
+        &[imutiable_registry_account.clone(), system_program.clone()],
+        &[registry_seeds],
+    )?;
+    invoke_signed(
+        &system_instruction::assign(&pda_imu, program_id),
+        &[imutiable_registry_account.clone(), system_program.clone()],
+        &[registry_seeds],
     )?;
-    let binding = &mut post_account.data.borrow();
-    let post_account_data = bytemuck::from_bytes::<InitAccountData>(&binding);
+    let mut binding = post_account.data.borrow_mut();
+    let post_account_data = load_mut::<InitAccountData>(&mut binding)?;
 
-    let mut binding = &mut imutiable_registry_account.data.borrow_mut();
-    let data_mut = bytemuck::from_bytes_mut::<ImmutableRegistryData>(&mut binding);
+    let mut binding = imutiable_registry_account.data.borrow_mut();
+    let data_mut = state::init::<ImmutableRegistryData>(&mut binding)?;
 
-    data_mut.buyer = data.buyer;
-    data_mut.item_id = data.item_id;
+    data_mut.buyer = deal.buyer;
+    data_mut.item_id = deal.item_id;
     data_mut.seller = post_account_data.payer;
     data_mut.description = post_account_data.description;
-    data_mut.timestamp = solana_program::sysvar::clock::Clock::get()?.unix_timestamp as u64;
+    data_mut.timestamp = Clock::get()?.unix_timestamp as u64;
     data_mut.title = post_account_data.title;
-    data_mut.price = post_account_data.price;
-
+    data_mut.price = deal.price; // the price locked at BUY , the one the escrow hold
+    data_mut.fee = fee;
+    data_mut.bump = bump_imu;
 
-    **seller.try_borrow_mut_lamports()? = seller
-        .lamports()
-        .checked_add(holder_account.lamports())
-        .ok_or(ProgramError::InvalidArgument)?;
-    **holder_account.try_borrow_mut_lamports()? = 0;
+    // the deal is done , the listing can't be bought , edited or reopened any more
+    post_account_data.set_status(ListingStatus::Sold)?;
 
-    **buyer.try_borrow_mut_lamports()? = buyer
-        .lamports()
-        .checked_add(buy_account.lamports())
-        .ok_or(ProgramError::InvalidArgument)?;
-    **buy_account.try_borrow_mut_lamports()? = 0;
 
-    let mut data = buy_account.try_borrow_mut_data()?;
-    data.fill(0);
-    msg!("Sell completed successfully");
+    // settle : the escrowed amount go to the seller and the treasury , the deal rent back to whoever funded it
+    settle_escrow(
+        deal_account,
+        &deal_left,
+        token.as_ref(),
+        &[treasury_share, seller_share],
+        rent_receiver,
+    )?;
+    msg!("Sell completed successfully , fee {} to the treasury", fee);
 
     Ok(())
 }
 
-fn temp_money_holder(
-    program_id: &Pubkey,
-    accounts : &[AccountInfo], //[seller,payer, holder_account , system_program]
-    data : &TempMoneyHolder
-)-> ProgramResult{
-    let account_iter = &mut accounts.iter();
-    let payer = next_account_info(account_iter)?;
-    let holder_account = next_account_info(account_iter)?;
-    let system_program = next_account_info(account_iter)?;
+// fund the registry rent shares. a party that signed pay its share from its wallet , or the relayer funding
+// the settlement pay it instead. the share of a party that did not sign is returned so the caller take it
+// from the proceeds. the treasury sponsor out of the lamports above its own rent reserve
+fn pay_registry_rent<'a>(
+    registry: &AccountInfo<'a>,
+    shares: &RentShares,
+    [buyer, seller]: [&AccountInfo<'a>; 2],
+    funder: Option<&AccountInfo<'a>>,
+    treasury: &AccountInfo<'a>,
+    system_program: &AccountInfo<'a>,
+) -> Result<u64, ProgramError> {
+    let mut from_proceeds = shares.proceeds;
+    for (party, share) in [(buyer, shares.buyer), (seller, shares.seller)] {
+        if share == 0 {
+            continue;
+        }
+        if party.is_signer {
+            let payer = funder.unwrap_or(party);
+            invoke(
+                &system_instruction::transfer(payer.key, registry.key, share),
+                &[payer.clone(), registry.clone(), system_program.clone()],
+            )?;
+        } else {
+            from_proceeds = from_proceeds
+                .checked_add(share)
+                .ok_or(MarketplaceError::ArithmeticOverflow)?;
+        }
+    }
 
-    if !payer.is_signer{
-        msg!("Error: payer must be a signer");
-        return Err(ProgramError::InvalidInstructionData)
+    if shares.treasury > 0 {
+        let reserve = Rent::get()?.minimum_balance(std::mem::size_of::<TreasuryData>());
+        let available = treasury.lamports().saturating_sub(reserve);
+        if shares.treasury > available {
+            msg!("Error: Treasury has {} lamports to sponsor the registry rent", available);
+            return Err(MarketplaceError::TreasuryInsufficient.into());
+        }
+        **treasury.try_borrow_mut_lamports()? -= shares.treasury;
+        **registry.try_borrow_mut_lamports()? = registry
+            .lamports()
+            .checked_add(shares.treasury)
+            .ok_or(MarketplaceError::ArithmeticOverflow)?;
+        msg!("Registry rent: {} sponsored by the treasury", shares.treasury);
     }
 
-    let (pda , bump ) = Pubkey::find_program_address(&[b"TEMP",data.buyer.as_ref(), data.seller.as_ref() , data.title.as_ref()], program_id);
+    Ok(from_proceeds)
+}
 
-    if *holder_account.key != pda{
-        msg!("Err: holder account pda dont match provide correct one ");
-        return Err(ProgramError::InvalidInstructionData)
+// create a PDA of this program at seeds , payer pay what is missing for the rent. we fund + allocate + assign
+// instead of create_account , that fail on an address that already hold lamports , so nobody can block the
+// account by sending lamports to its address first
+fn create_pda<'a>(
+    program_id: &Pubkey,
+    payer: &AccountInfo<'a>,
+    account: &AccountInfo<'a>,
+    size: usize,
+    seeds: &[&[u8]],
+    system_program: &AccountInfo<'a>,
+) -> ProgramResult {
+    let missing = Rent::get()?.minimum_balance(size).saturating_sub(account.lamports());
+    if missing > 0 {
+        invoke(
+            &system_instruction::transfer(payer.key, account.key, missing),
+            &[payer.clone(), account.clone(), system_program.clone()],
+        )?;
     }
-    let rent = Rent::get()?;
-    let min_lamp = rent.minimum_balance(0);
-    let ix = instruction::create_account(
-        payer.key, 
-        &pda, 
-        min_lamp, 
-        0, 
-        program_id
-    );
-
     invoke_signed(
-        &ix, 
-        &[payer.clone(),holder_account.clone(), system_program.clone()], 
-        &[&[b"TEMP",data.buyer.as_ref(), data.seller.as_ref() , data.title.as_ref() , &[bump]]])?;
+        &system_instruction::allocate(account.key, size as u64),
+        &[account.clone(), system_program.clone()],
+        &[seeds],
+    )?;
+    invoke_signed(
+        &system_instruction::assign(account.key, program_id),
+        &[account.clone(), system_program.clone()],
+        &[seeds],
+    )
+}
 
-    msg!("succesfully temp_money_holder created!!!!!");
+// the config singleton , checked by its seeds so no look alike account can set the fee
+fn load_config(config_account: Account) -> Result<ConfigData, ProgramError> {
+    let (config_account, _) = config_account.stored_pda::<ConfigData>(&[b"CONFIG"], MarketplaceError::WrongConfigPda)?;
+    let config_account = config_account.info();
+    Ok(*load::<ConfigData>(&config_account.data.borrow())?)
+}
 
+// the config rules a listing must follow on INIT and on every UPDATE
+fn check_listing_policy(config: &ConfigData, price: u64, arbiter: &[u8; 32]) -> ProgramResult {
+    if let Err(e) = config.check_price(price) {
+        msg!("Error: Price {} is above the marketplace limit {}", price, config.max_price);
+        return Err(e.into());
+    }
+    if !config.is_arbiter_allowed(arbiter) {
+        msg!("Error: Arbiter is not on the marketplace arbiter list");
+        return Err(MarketplaceError::ArbiterNotApproved.into());
+    }
     Ok(())
+}
 
+// a paused operation fail , what is paused is only logged
+fn check_not_paused(paused: u8, what: &str) -> ProgramResult {
+    if paused != 0 {
+        msg!("Error: {} are paused", what);
+        return Err(MarketplaceError::MarketplacePaused.into());
+    }
+    Ok(())
 }
 
+// the signer is the admin named in the config
+fn check_admin(admin: Account, config_account: Account) -> ProgramResult {
+    let admin = admin.signer()?.info();
+    let config = load_config(config_account)?;
+    if config.admin != admin.key.to_bytes() {
+        msg!("Error: Admin account mismatch");
+        return Err(MarketplaceError::NotAdmin.into());
+    }
+    Ok(())
+}
 
-fn money_holder(
-    program_id: &Pubkey,
-    accounts : &[AccountInfo],
-    data : &MoneyHolder
-)-> ProgramResult{
-    let account_iter = &mut accounts.iter();
-    let payer = next_account_info(account_iter)?;
-    let holder_account = next_account_info(account_iter)?;
-    let system_program = next_account_info(account_iter)?;
+// the treasury PDA , return its stored bump for signing token withdrawals
+fn check_treasury(treasury: Account) -> Result<u8, ProgramError> {
+    let (_, bump) = treasury.stored_pda::<TreasuryData>(&[b"TREASURY"], MarketplaceError::WrongTreasuryPda)?;
+    Ok(bump)
+}
+
+// who fund the rent of an account created here : a relayer can sign as an extra account after all the others
+// so an owner with no SOL can still create it , without it the owner pay as before
+fn rent_funder<'a, 'b>(
+    accounts: &mut Accounts<'a, 'b>,
+    owner: &'b AccountInfo<'a>,
+) -> Result<&'b AccountInfo<'a>, ProgramError> {
+    match accounts.next_optional()? {
+        Some(funder) => Ok(funder.signer()?.writable()?.info()),
+        None => Ok(owner),
+    }
+}
 
-    if !payer.is_signer{
-        msg!("Error: payer must be a signer");
-        return Err(ProgramError::InvalidInstructionData)
+// who get the rent back when an account close : the funder recorded at creation. that is the owner (checked
+// writable by the caller) unless a relayer funded it , then the relayer is passed after all the other accounts.
+// the key is pinned by the record , so it may be an account passed before (a relayer that crank its own deal)
+fn rent_receiver<'a, 'b>(
+    accounts: &mut Accounts<'a, 'b>,
+    rent_payer: &[u8; 32],
+    owner: &'b AccountInfo<'a>,
+) -> Result<&'b AccountInfo<'a>, ProgramError> {
+    if owner.key.to_bytes() == *rent_payer {
+        return Ok(owner);
     }
+    Ok(accounts
+        .next_shared()?
+        .is_key(&Pubkey::new_from_array(*rent_payer), MarketplaceError::EscrowPayeeMismatch)?
+        .writable()?
+        .info())
+}
 
-    let (pda , bump ) = Pubkey::find_program_address(&[b"HOLDER", data.title.as_ref()], program_id);
+// the listing of owner at its seeds , owner and header checked
+fn listing_of<'a, 'b>(
+    listing: Account<'a, 'b>,
+    seed: &[u8],
+    owner: &AccountInfo,
+) -> Result<&'b AccountInfo<'a>, ProgramError> {
+    let (listing, _) = listing
+        .writable()?
+        .stored_pda::<InitAccountData>(&[b"INIT", seed, owner.key.as_ref()], MarketplaceError::WrongListingPda)?;
+    Ok(listing.info())
+}
 
-    if *holder_account.key != pda{
-        msg!("Err: holder account pda dont match provide correct one ");
-        return Err(ProgramError::InvalidInstructionData)
+// the deal and the listing it reserved , using the key BUY stored , nothing here come from client seeds.
+// the handler declared both accounts with holds , so each is program owned with the right header , and a
+// program owned account with a valid DealData header can only have been written by BUY , so the stored keys can be trusted
+fn load_deal(deal_account: &AccountInfo, post_account: &AccountInfo) -> Result<DealData, ProgramError> {
+    let deal = *load::<DealData>(&deal_account.data.borrow())?;
+
+    if deal.listing != post_account.key.to_bytes() {
+        msg!("Error: Post account does not belong to this deal");
+        return Err(MarketplaceError::WrongListingPda.into());
     }
 
-    let rent = Rent::get()?;
-    let min_lamp = rent.minimum_balance(0);
-    let ix = instruction::create_account(
-        payer.key, 
-        &pda, 
-        min_lamp, 
-        0, 
-        program_id
-    );
+    Ok(deal)
+}
 
-    invoke_signed(
-        &ix, 
-        &[payer.clone(),holder_account.clone(), system_program.clone()], 
-        &[&[b"TEMP", data.title.as_ref() , &[bump]]])?;
+// the token side of a deal paid in tokens , read from the accounts that follow the usual ones
+struct TokenEscrow<'a, 'b> {
+    mint: &'b AccountInfo<'a>,
+    vault: &'b AccountInfo<'a>, // the ATA of the deal PDA
+    token_program: &'b AccountInfo<'a>,
+    mint_info: token::MintInfo,
+}
+
+// one share of the escrow , token_account is where the wallet get it in a token deal
+struct Payout<'a, 'b> {
+    wallet: &'b AccountInfo<'a>,
+    token_account: Option<&'b AccountInfo<'a>>,
+    amount: u64,
+}
+
+// a lamport escrow has no token accounts , a token escrow must be given the exact mint and vault BUY recorded
+fn load_token_escrow<'a, 'b>(
+    accounts: &mut Accounts<'a, 'b>,
+    deal: &DealData,
+) -> Result<Option<TokenEscrow<'a, 'b>>, ProgramError> {
+    if deal.mint == [0; 32] {
+        return Ok(None);
+    }
+    let mint = accounts
+        .next_account()?
+        .is_key(&Pubkey::new_from_array(deal.mint), MarketplaceError::WrongMint)?
+        .info();
+    let vault = accounts
+        .next_account()?
+        .writable()?
+        .is_key(&Pubkey::new_from_array(deal.token_account), MarketplaceError::WrongTokenVault)?
+        .info();
+    let token_program = accounts
+        .next_account()?
+        .is_key(mint.owner, MarketplaceError::WrongTokenProgram)?
+        .info();
+    let mint_info = token::read_mint(mint)?;
+
+    Ok(Some(TokenEscrow {
+        mint,
+        vault,
+        token_program,
+        mint_info,
+    }))
+}
 
-    msg!("succesfully money_holder_account created!!!!!");
+// the share of wallet , in a token deal the next account is the wallet token account for the escrow mint
+fn next_payout<'a, 'b>(
+    accounts: &mut Accounts<'a, 'b>,
+    token: Option<&TokenEscrow<'a, '_>>,
+    wallet: &'b AccountInfo<'a>,
+    amount: u64,
+) -> Result<Payout<'a, 'b>, ProgramError> {
+    let token_account = match token {
+        None => None,
+        Some(token) => {
+            let account = accounts.next_account()?.writable()?.info();
+            check_token_account(account, token.token_program.key, token.mint.key, wallet.key)?;
+            Some(account)
+        }
+    };
+    Ok(Payout {
+        wallet,
+        token_account,
+        amount,
+    })
+}
 
+fn check_token_account(
+    account: &AccountInfo,
+    token_program: &Pubkey,
+    mint: &Pubkey,
+    owner: &Pubkey,
+) -> ProgramResult {
+    let state = token::read_token_account(account, token_program)?;
+    if state.mint != *mint || state.owner != *owner {
+        msg!("Error: Token account {} is not a {} account of {}", account.key, mint, owner);
+        return Err(MarketplaceError::TokenAccountMismatch.into());
+    }
     Ok(())
 }
 
+// pay the escrow out in shares and close the deal. the shares must add up to deal.amount exactly , lamports
+// move directly , tokens move out of the deal ATA signed by the deal PDA. then the deal (and its ATA) rent
+// goes to rent_receiver. the caller check that each wallet is the right side of the deal , here we check the
+// rent side and that no lamport appear or disappear between the deal , the payouts and the rent receiver
+fn settle_escrow<'a>(
+    deal_account: &AccountInfo<'a>,
+    deal: &DealData,
+    token: Option<&TokenEscrow<'a, '_>>,
+    payouts: &[Payout<'a, '_>],
+    rent_receiver: &AccountInfo<'a>,
+) -> ProgramResult {
+    let mut total: u64 = 0;
+    for payout in payouts {
+        total = total
+            .checked_add(payout.amount)
+            .ok_or(MarketplaceError::ArithmeticOverflow)?;
+    }
+    if total != deal.amount {
+        msg!("Error: Shares {} do not add up to the escrow {}", total, deal.amount);
+        return Err(MarketplaceError::LamportImbalance.into());
+    }
 
-fn info_account(
-    program_id: &Pubkey,
-    accounts : &[AccountInfo], //[seller , buyer]
-    data : &Info
-) -> ProgramResult{
-    let account_iter = &mut accounts.iter();
-    let seller = next_account_info(account_iter)?;
-    let buyer = next_account_info(account_iter)?;
-    let mut_account = next_account_info(account_iter)?;
-    let system_program = next_account_info(account_iter)?;
+    if deal.rent_payer != rent_receiver.key.to_bytes() {
+        msg!("Error: Rent receiver did not fund this deal");
+        return Err(MarketplaceError::EscrowPayeeMismatch.into());
+    }
 
+    // every account this function move lamports between , the vault rent goes to rent_receiver on close
+    let mut involved = vec![deal_account, rent_receiver];
+    involved.extend(payouts.iter().map(|payout| payout.wallet));
+    involved.extend(payouts.iter().filter_map(|payout| payout.token_account));
+    if let Some(token) = token {
+        involved.push(token.vault);
+    }
+    let total_before = lamport_total(&involved)?;
 
-    if !seller.is_signer && !buyer.is_signer{
-        msg!("Error : Either seller or buyer or even both may be not signer so plz provide valid signer");
-        return Err(ProgramError::InvalidInstructionData)
+    match token {
+        None => {
+            let mut rest = *deal;
+            for payout in payouts {
+                take_from_escrow(deal_account, &mut rest, payout.wallet, payout.amount)?;
+            }
+        }
+        Some(token) => {
+            // the deal was created by BUY at these seeds , its stored bump sign without a search
+            let deal_seeds: &[&[u8]] = &[b"DEAL", &deal.seed, &deal.buyer, &[deal.bump]];
+
+            // tokens somebody sent to the vault on top of the escrow go with the last share , the vault
+            // can only close once it is empty
+            let extra = token::read_token_account(token.vault, token.token_program.key)?
+                .amount
+                .checked_sub(deal.amount)
+                .ok_or(MarketplaceError::EscrowUnderfunded)?;
+
+            for (i, payout) in payouts.iter().enumerate() {
+                let mut amount = payout.amount;
+                if i == payouts.len() - 1 {
+                    amount = amount
+                        .checked_add(extra)
+                        .ok_or(MarketplaceError::ArithmeticOverflow)?;
+                }
+                if amount == 0 {
+                    continue;
+                }
+                let to = payout
+                    .token_account
+                    .ok_or(MarketplaceError::InvalidTokenAccount)?;
+                invoke_signed(
+                    &token::transfer_checked(
+                        token.token_program.key,
+                        token.vault.key,
+                        token.mint.key,
+                        to.key,
+                        deal_account.key,
+                        amount,
+                        token.mint_info.decimals,
+                    ),
+                    &[
+                        token.vault.clone(),
+                        token.mint.clone(),
+                        to.clone(),
+                        deal_account.clone(),
+                        token.token_program.clone(),
+                    ],
+                    &[deal_seeds],
+                )?;
+            }
+
+            // Token-2022 refuse to close an account that still hold withheld transfer fees
+            if token.mint_info.transfer_fee {
+                invoke(
+                    &token::harvest_withheld_tokens_to_mint(
+                        token.token_program.key,
+                        token.mint.key,
+                        &[token.vault.key],
+                    ),
+                    &[
+                        token.mint.clone(),
+                        token.vault.clone(),
+                        token.token_program.clone(),
+                    ],
+                )?;
+            }
+            invoke_signed(
+                &token::close_account(
+                    token.token_program.key,
+                    token.vault.key,
+                    rent_receiver.key,
+                    deal_account.key,
+                ),
+                &[
+                    token.vault.clone(),
+                    rent_receiver.clone(),
+                    deal_account.clone(),
+                    token.token_program.clone(),
+                ],
+                &[deal_seeds],
+            )?;
+        }
     }
 
-    let (pda , bump) = Pubkey::find_program_address(&[b"IMUTABLE",data.title.as_ref(), buyer.key.as_ref(), seller.key.as_ref()], program_id);
+    // the rent reserve and anything somebody sent to the deal by mistake , all of it goes to the funder
+    let rent_back = deal_account.lamports();
+    state::close(deal_account, rent_receiver)?;
 
-    if *mut_account.key != pda{
-        msg!("Error: the pda of an IMUTABLE account is wrong ");
-        return Err(ProgramError::InvalidInstructionData)
+    let total_after = lamport_total(&involved)?;
+    if total_before != total_after {
+        msg!("Error: Escrow settlement does not balance");
+        return Err(MarketplaceError::LamportImbalance.into());
     }
 
-    let rent = Rent::get()?;
-    let size = std::mem::size_of::<ImmutableRegistryData>() as u64;
-    let min_lamp = rent.minimum_balance(size as usize);
+    msg!("Escrow released: {} in {} shares, {} to rent payer", deal.amount, payouts.len(), rent_back);
+    Ok(())
+}
 
-    let ix = instruction::create_account(
-        buyer.key,
-         &pda,
-          min_lamp, 
-          size, 
-          program_id
-        );
+// pay part of the escrowed amount out before the deal is closed , deal is the caller copy of the
+// deal record and its amount goes down by what was paid so the shares still add up in settle_escrow
+fn take_from_escrow(
+    deal_account: &AccountInfo,
+    deal: &mut DealData,
+    to: &AccountInfo,
+    lamports: u64,
+) -> ProgramResult {
+    deal.amount = deal
+        .amount
+        .checked_sub(lamports)
+        .ok_or(MarketplaceError::EscrowUnderfunded)?;
+    **deal_account.try_borrow_mut_lamports()? = deal_account
+        .lamports()
+        .checked_sub(lamports)
+        .ok_or(MarketplaceError::EscrowUnderfunded)?;
+    **to.try_borrow_mut_lamports()? = to
+        .lamports()
+        .checked_add(lamports)
+        .ok_or(MarketplaceError::ArithmeticOverflow)?;
+    Ok(())
+}
 
-    invoke_signed(
-        &ix, 
-        &[
-            buyer.clone(),
-            mut_account.clone(),
-            system_program.clone()
-        ], 
-        &[&[
-            b"IMUTABLE",
-            data.title.as_ref(),
-            seller.key.as_ref(),
-            &[bump]
-        ]]
+// sum of the lamports of the accounts , an account passed twice (a payout wallet that is also the rent receiver) is counted once
+fn lamport_total(accounts: &[&AccountInfo<'_>]) -> Result<u64, ProgramError> {
+    let mut total: u64 = 0;
+    for (i, account) in accounts.iter().enumerate() {
+        if accounts[..i].iter().any(|seen| seen.key == account.key) {
+            continue;
+        }
+        total = total
+            .checked_add(account.lamports())
+            .ok_or(MarketplaceError::ArithmeticOverflow)?;
+    }
+    Ok(total)
+}
+
+// create the config and the treasury once. the ProgramData account of this program name its upgrade authority ,
+// only that key can be the first admin so nobody can take the marketplace by sending INIT_CONFIG first
+fn init_config(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo], //[admin , config , treasury , program_data , system_program]
+    ix_data: &InitConfigData,
+) -> ProgramResult {
+    let mut accounts = Accounts::new(program_id, accounts);
+    let admin = accounts.next_account()?.signer()?.writable()?.info();
+    let (config_account, config_bump) =
+        accounts.next_account()?.writable()?.find_pda(&[b"CONFIG"], MarketplaceError::WrongConfigPda)?;
+    let config_account = config_account.info();
+    let (treasury, treasury_bump) =
+        accounts.next_account()?.writable()?.find_pda(&[b"TREASURY"], MarketplaceError::WrongTreasuryPda)?;
+    let treasury = treasury.info();
+    let program_data = accounts.next_account()?.info(); // derived under the loader , checked below
+    let system_program = accounts.next_account()?.system_program()?.info();
+
+    let (program_data_key, _) =
+        Pubkey::find_program_address(&[program_id.as_ref()], &BPF_LOADER_UPGRADEABLE_ID);
+    if program_data_key != *program_data.key || *program_data.owner != BPF_LOADER_UPGRADEABLE_ID {
+        msg!("Error: Program data account is not the one of this program");
+        return Err(MarketplaceError::WrongProgramData.into());
+    }
+    // ProgramData : [ {tag u32 = 3} , {slot u64} , {authority Option<Pubkey>} , ... ]
+    {
+        let data = program_data.data.borrow();
+        if data.len() < 45 || data[0..4] != 3u32.to_le_bytes() {
+            msg!("Error: Program data account is malformed");
+            return Err(MarketplaceError::WrongProgramData.into());
+        }
+        if data[12] != 1 || data[13..45] != admin.key.to_bytes() {
+            msg!("Error: Signer is not the upgrade authority");
+            return Err(MarketplaceError::NotAdmin.into());
+        }
+    }
+
+    if ix_data.fee_bps > MAX_FEE_BPS {
+        msg!("Error: Fee of {} basis points", ix_data.fee_bps);
+        return Err(MarketplaceError::InvalidFeeBps.into());
+    }
+
+    // both addresses are known before the program is even deployed , lamports sent there must not block the setup
+    create_pda(
+        program_id,
+        admin,
+        config_account,
+        std::mem::size_of::<ConfigData>(),
+        &[b"CONFIG", &[config_bump]],
+        system_program,
+    )?;
+    create_pda(
+        program_id,
+        admin,
+        treasury,
+        std::mem::size_of::<TreasuryData>(),
+        &[b"TREASURY", &[treasury_bump]],
+        system_program,
     )?;
 
+    {
+        let mut binding = config_account.data.borrow_mut();
+        let config = state::init::<ConfigData>(&mut binding)?;
+        config.admin = admin.key.to_bytes();
+        config.fee_bps = ix_data.fee_bps;
+        config.accept_window = ACCEPT_WINDOW;
+        config.confirm_window = DELIVERY_CONFIRM_WINDOW;
+        config.bump = config_bump;
+        // no price limit and no arbiters until the admin send SET_CONFIG
+    }
+    state::init::<TreasuryData>(&mut treasury.data.borrow_mut())?.bump = treasury_bump;
+
+    msg!("Config initialized , fee {} bps", ix_data.fee_bps);
+    Ok(())
+}
+
+// the admin take collected fees out. lamports above the treasury rent reserve , or tokens from the treasury ATA
+fn withdraw_treasury(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo], //[admin , config , treasury , destination] + for a token withdraw [mint , treasury_token_account , token_program , destination_token_account]
+    ix_data: &WithdrawTreasuryData,
+) -> ProgramResult {
+    let mut accounts = Accounts::new(program_id, accounts);
+    let admin = accounts.next_account()?;
+    let config_account = accounts.next_account()?;
+    check_admin(admin, config_account)?;
+    let treasury = accounts.next_account()?.writable()?;
+    let treasury_bump = check_treasury(treasury)?;
+    let treasury = treasury.info();
+    let destination = accounts.next_shared()?.writable()?.info(); // may be the admin wallet
+
+    if ix_data.mint == [0; 32] {
+        let reserve = Rent::get()?.minimum_balance(std::mem::size_of::<TreasuryData>());
+        let available = treasury.lamports().saturating_sub(reserve);
+        if ix_data.amount > available {
+            msg!("Error: Treasury has {} lamports to withdraw", available);
+            return Err(MarketplaceError::TreasuryInsufficient.into());
+        }
+        **treasury.try_borrow_mut_lamports()? = treasury
+            .lamports()
+            .checked_sub(ix_data.amount)
+            .ok_or(MarketplaceError::ArithmeticOverflow)?;
+        **destination.try_borrow_mut_lamports()? = destination
+            .lamports()
+            .checked_add(ix_data.amount)
+            .ok_or(MarketplaceError::ArithmeticOverflow)?;
+    } else {
+        let mint = accounts
+            .next_account()?
+            .is_key(&Pubkey::new_from_array(ix_data.mint), MarketplaceError::WrongMint)?
+            .info();
+        let treasury_token_account = accounts.next_account()?.writable()?;
+        let token_program = accounts
+            .next_account()?
+            .is_key(mint.owner, MarketplaceError::WrongTokenProgram)?
+            .info();
+        let treasury_token_account = treasury_token_account
+            .is_key(
+                &token::get_associated_token_address(treasury.key, mint.key, token_program.key),
+                MarketplaceError::TokenAccountMismatch,
+            )?
+            .info();
+        let destination_token_account = accounts.next_account()?.writable()?.info();
+        let mint_info = token::read_mint(mint)?;
+        let held = token::read_token_account(treasury_token_account, token_program.key)?.amount;
+        if ix_data.amount > held {
+            msg!("Error: Treasury has {} tokens to withdraw", held);
+            return Err(MarketplaceError::TreasuryInsufficient.into());
+        }
+        check_token_account(
+            destination_token_account,
+            token_program.key,
+            mint.key,
+            destination.key,
+        )?;
+
+        invoke_signed(
+            &token::transfer_checked(
+                token_program.key,
+                treasury_token_account.key,
+                mint.key,
+                destination_token_account.key,
+                treasury.key,
+                ix_data.amount,
+                mint_info.decimals,
+            ),
+            &[
+                treasury_token_account.clone(),
+                mint.clone(),
+                destination_token_account.clone(),
+                treasury.clone(),
+                token_program.clone(),
+            ],
+            &[&[b"TREASURY", &[treasury_bump]]],
+        )?;
+    }
+
+    msg!("Event: TreasuryWithdrawn amount={} to={}", ix_data.amount, destination.key);
+    Ok(())
+}
+
+// the admin replace the policy. running deals keep the fee and windows they locked at BUY , listings are
+// checked against the new price limit and arbiter list the next time they are edited
+fn set_config(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo], //[admin , config]
+    ix_data: &SetConfigData,
+) -> ProgramResult {
+    let mut accounts = Accounts::new(program_id, accounts);
+    let admin = accounts.next_account()?;
+    let config_account = accounts.next_account()?.writable()?;
+    check_admin(admin, config_account)?;
+    let config_account = config_account.info();
+
+    if ix_data.fee_bps > MAX_FEE_BPS {
+        msg!("Error: Fee of {} basis points", ix_data.fee_bps);
+        return Err(MarketplaceError::InvalidFeeBps.into());
+    }
+    if ix_data.accept_window <= 0 || ix_data.confirm_window <= 0 {
+        msg!("Error: Deal windows must be positive");
+        return Err(MarketplaceError::InvalidWindow.into());
+    }
+    if let Err(e) = RegistryRentPolicy::from_u8(ix_data.registry_rent_policy) {
+        msg!("Error: Registry rent policy {}", ix_data.registry_rent_policy);
+        return Err(e.into());
+    }
+
+    let mut binding = config_account.data.borrow_mut();
+    let config = load_mut::<ConfigData>(&mut binding)?;
+    config.fee_bps = ix_data.fee_bps;
+    config.max_price = ix_data.max_price;
+    config.accept_window = ix_data.accept_window;
+    config.confirm_window = ix_data.confirm_window;
+    config.arbiters = ix_data.arbiters;
+    config.registry_rent_policy = ix_data.registry_rent_policy;
+
+    msg!(
+        "Event: ConfigUpdated fee_bps={} max_price={} accept_window={} confirm_window={} registry_rent_policy={}",
+        config.fee_bps,
+        config.max_price,
+        config.accept_window,
+        config.confirm_window,
+        config.registry_rent_policy
+    );
     Ok(())
 }
 
+// the incident switch. CANCEL , REJECT and EXPIRE don't read it so buyers can always get their money back
+fn set_pause(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo], //[admin , config]
+    ix_data: &SetPauseData,
+) -> ProgramResult {
+    let mut accounts = Accounts::new(program_id, accounts);
+    let admin = accounts.next_account()?;
+    let config_account = accounts.next_account()?.writable()?;
+    check_admin(admin, config_account)?;
+    let config_account = config_account.info();
+
+    let mut binding = config_account.data.borrow_mut();
+    let config = load_mut::<ConfigData>(&mut binding)?;
+    config.pause_listings = (ix_data.listings != 0) as u8;
+    config.pause_buys = (ix_data.buys != 0) as u8;
+    config.pause_settlements = (ix_data.settlements != 0) as u8;
+
+    msg!(
+        "Event: PauseChanged listings={} buys={} settlements={}",
+        config.pause_listings,
+        config.pause_buys,
+        config.pause_settlements
+    );
+    Ok(())
+}
 
+// first step of the admin hand over , the current admin keep every right until the new one accept
+fn propose_admin(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo], //[admin , config]
+    ix_data: &ProposeAdminData,
+) -> ProgramResult {
+    let mut accounts = Accounts::new(program_id, accounts);
+    let admin = accounts.next_account()?;
+    let config_account = accounts.next_account()?.writable()?;
+    check_admin(admin, config_account)?;
+    let config_account = config_account.info();
+
+    load_mut::<ConfigData>(&mut config_account.data.borrow_mut())?.pending_admin = ix_data.new_admin;
+
+    msg!("Event: AdminProposed pending={}", Pubkey::new_from_array(ix_data.new_admin));
+    Ok(())
+}
+
+// second step , the proposed key sign so a typo in PROPOSE_ADMIN can never lock the marketplace
+fn accept_admin(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo], //[new_admin , config]
+) -> ProgramResult {
+    let mut accounts = Accounts::new(program_id, accounts);
+    let new_admin = accounts.next_account()?.signer()?.info();
+    let config_account = accounts.next_account()?.writable()?;
+    load_config(config_account)?;
+    let config_account = config_account.info();
+
+    let mut binding = config_account.data.borrow_mut();
+    let config = load_mut::<ConfigData>(&mut binding)?;
+    if config.pending_admin == [0; 32] || config.pending_admin != new_admin.key.to_bytes() {
+        msg!("Error: Signer is not the proposed admin");
+        return Err(MarketplaceError::NotPendingAdmin.into());
+    }
+    config.admin = config.pending_admin;
+    config.pending_admin = [0; 32];
+
+    msg!("Event: AdminChanged admin={}", new_admin.key);
+    Ok(())
+}
@@ -0,0 +1,655 @@
+/*
+    the data we keep inside the program owned accounts.
+
+    every account start with the same 16 byte AccountHeader :
+        [ {discriminator 8 byte} , {layout version 1 byte} , {padding 7 byte} ]
+    the discriminator tell which struct the account hold , so a deal account can never be read as a listing and so on.
+    indexers can filter the accounts of one kind with a memcmp on offset 0.
+    the version is bumped when the layout of the struct after the header change.
+
+    nothing in the program cast account data with bytemuck directly , it always goes through load / load_mut / init
+    so the size , the discriminator and the version are checked before we touch the fields.
+    and every account we close goes through close , so a closed account is empty and owned by the system program.
+*/
+
+use bytemuck::{Pod, Zeroable};
+use solana_program::{account_info::AccountInfo, entrypoint::ProgramResult};
+
+use crate::error::MarketplaceError;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Pod, Zeroable)]
+pub struct AccountHeader {
+    pub discriminator: [u8; 8],
+    pub version: u8,
+    pub _padding: [u8; 7],
+}
+
+// implemented by every struct we store in a program owned account , the struct must start with an AccountHeader
+pub trait ProgramAccount: Pod {
+    const DISCRIMINATOR: [u8; 8];
+    const VERSION: u8;
+}
+
+// a PDA that keep the canonical bump it was created with , so later instructions check its address with one
+// create_program_address instead of a find_program_address search
+pub trait StoredBump: ProgramAccount {
+    fn bump(&self) -> u8;
+}
+
+// the main data structer for an account data that we store in side the account
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Pod, Zeroable)]
+pub struct InitAccountData {
+    pub header: AccountHeader,
+    pub item_id: [u8; 32],
+    pub title: [u8; 128],
+    pub description: [u8; 1024],
+    pub price: u64,
+    pub payer: [u8; 32],
+    pub status: u8, // ListingStatus as u8 , Draft = 0 and Active = 1 so it match the old published flag
+    pub bump: u8,   // of ["INIT", seed, payer]
+    pub _padding: [u8; 2],
+    pub revision: u32, // bumped on every edit , BUY compare it with what the buyer saw. was padding so old listings read 0
+    pub arbiter: [u8; 32], // who rule a dispute on a deal of this listing , all zero = no dispute possible
+    pub payment_mint: [u8; 32], // the price is in this SPL Token / Token-2022 mint , all zero = lamports
+    pub rent_payer: [u8; 32], // who funded the rent , the owner or a relayer. DELETE give the rent back to it
+}
+
+impl ProgramAccount for InitAccountData {
+    const DISCRIMINATOR: [u8; 8] = *b"LISTING_";
+    const VERSION: u8 = 5;
+}
+
+impl StoredBump for InitAccountData {
+    fn bump(&self) -> u8 {
+        self.bump
+    }
+}
+
+/*
+    life of a listing :
+
+        INIT -> Draft --PUBLISH--> Active --BUY--> Reserved --SELL / CLAIM--> Sold
+                  ^                  |  ^                |
+                  +--UPDATE(reopen)--+  +-CANCEL/EXPIRE/REJECT-+
+
+    DELETE move Draft , Active or Sold to Withdrawn and close the account , it is refused while Reserved.
+    UPDATE (title / price) is only allowed in Draft and Active , the price is locked while a buy is pending.
+*/
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ListingStatus {
+    Draft = 0,     // description still uploading , not for sale
+    Active = 1,    // content locked and the post is live
+    Reserved = 2,  // a buyer has money in escrow for it
+    Sold = 3,      // SELL went through
+    Withdrawn = 4, // removed by the owner
+}
+
+impl ListingStatus {
+    pub fn from_u8(value: u8) -> Result<Self, MarketplaceError> {
+        match value {
+            0 => Ok(Self::Draft),
+            1 => Ok(Self::Active),
+            2 => Ok(Self::Reserved),
+            3 => Ok(Self::Sold),
+            4 => Ok(Self::Withdrawn),
+            _ => Err(MarketplaceError::InvalidAccountLayout),
+        }
+    }
+
+    // every legal move of the state machine above
+    pub fn can_become(self, next: Self) -> bool {
+        use ListingStatus::*;
+        matches!(
+            (self, next),
+            (Draft, Active)
+                | (Active, Draft)
+                | (Active, Reserved)
+                | (Reserved, Active)
+                | (Reserved, Sold)
+                | (Draft, Withdrawn)
+                | (Active, Withdrawn)
+                | (Sold, Withdrawn)
+        )
+    }
+
+    // the title and the price can be edited only before a buyer show up
+    pub fn is_editable(self) -> bool {
+        matches!(self, Self::Draft | Self::Active)
+    }
+}
+
+impl InitAccountData {
+    pub fn status(&self) -> Result<ListingStatus, MarketplaceError> {
+        ListingStatus::from_u8(self.status)
+    }
+
+    // move the listing to next , refuse anything the state machine don't allow
+    pub fn set_status(&mut self, next: ListingStatus) -> Result<(), MarketplaceError> {
+        let current = self.status()?;
+        if !current.can_become(next) {
+            return Err(listing_status_error(current));
+        }
+        self.status = next as u8;
+        Ok(())
+    }
+
+    // for the handlers that change fields but not the status
+    pub fn check_editable(&self) -> Result<(), MarketplaceError> {
+        let current = self.status()?;
+        if !current.is_editable() {
+            return Err(listing_status_error(current));
+        }
+        Ok(())
+    }
+
+    // called by every handler that change the title , the price or the description
+    pub fn bump_revision(&mut self) -> Result<(), MarketplaceError> {
+        self.revision = self
+            .revision
+            .checked_add(1)
+            .ok_or(MarketplaceError::ArithmeticOverflow)?;
+        Ok(())
+    }
+}
+
+// a pending buy get its own error so the frontend can tell the seller why
+fn listing_status_error(current: ListingStatus) -> MarketplaceError {
+    if current == ListingStatus::Reserved {
+        MarketplaceError::ListingReserved
+    } else {
+        MarketplaceError::IllegalListingTransition
+    }
+}
+
+// one deal , created by BUY at ["DEAL", seed, buyer]. the account is the deal record and the escrow at once :
+// its lamports are the rent reserve plus the escrowed amount , and for a token deal the escrow sit in the ATA
+// of this account. settlement and refunds pay amount out of it and then close it , so nothing else need to be
+// created or passed around for a deal
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Pod, Zeroable)]
+pub struct DealData {
+    pub header: AccountHeader,
+    pub item_id: [u8; 32], // read from the listing , not from the client
+    pub listing: [u8; 32], // the listing account this deal reserved
+    pub buyer: [u8; 32],   // the signer of BUY , put the amount in and get it back on refund
+    pub seller: [u8; 32],  // get the amount minus the fee when the deal settle
+    pub price: u64, // locked at BUY , this is what SELL settle whatever happen to the listing after
+    pub status: u8, // DealStatus as u8
+    pub bump: u8,   // of ["DEAL", seed, buyer] , sign the token payouts
+    pub _padding: [u8; 6],
+    pub shipped_at: i64,       // unix time of SHIP , 0 before that
+    pub confirm_deadline: i64, // after this the seller can CLAIM without the buyer confirmation , 0 before SHIP
+    pub created_at: i64,       // unix time of BUY
+    pub accept_deadline: i64,  // the seller must SHIP before this , after it anyone can EXPIRE the deal
+    pub arbiter: [u8; 32],     // copied from the listing at BUY , a later edit of the listing don't change it
+    pub buyer_evidence: [u8; 32],  // latest evidence hash the buyer attached , all zero = none
+    pub seller_evidence: [u8; 32], // same for the seller
+    pub disputed_at: i64,      // unix time of OPEN_DISPUTE , 0 if never disputed
+    pub fee_bps: u16,          // marketplace fee locked at BUY , SELL take it from the seller payout
+    pub registry_rent_policy: u8, // config policy locked at BUY like the fee. was padding so old deals read 0 = Settler
+    pub _padding2: [u8; 5],
+    pub confirm_window: i64,   // config.confirm_window at BUY , SHIP add it to the ship time
+    pub seed: [u8; 32],        // the BUY seed , part of the signer seeds of the deal
+    pub amount: u64,           // lamports (or tokens of mint) held for the deal , not counting the rent reserve
+    pub rent_payer: [u8; 32],  // who funded the rent reserve , get it back when the deal close
+    pub mint: [u8; 32],        // all zero = the amount is lamports in this account
+    pub token_account: [u8; 32], // for a token deal , the ATA of this account that hold the tokens
+}
+
+impl ProgramAccount for DealData {
+    const DISCRIMINATOR: [u8; 8] = *b"DEAL____";
+    const VERSION: u8 = 1;
+}
+
+impl StoredBump for DealData {
+    fn bump(&self) -> u8 {
+        self.bump
+    }
+}
+
+// default of config.accept_window : how long the seller has to accept and ship once the buyer paid
+pub const ACCEPT_WINDOW: i64 = 3 * 24 * 60 * 60;
+
+// default of config.confirm_window : how long the buyer has to confirm the receipt once the seller marked the deal shipped
+pub const DELIVERY_CONFIRM_WINDOW: i64 = 7 * 24 * 60 * 60;
+
+// paid out of the escrow to whoever crank EXPIRE for the buyer , so stale deals get cleaned without the buyer
+pub const EXPIRE_CRANK_BOUNTY: u64 = 100_000;
+// but never more than this part of the escrow , so a cheap deal is not eaten by the bounty
+pub const EXPIRE_CRANK_BOUNTY_MAX_BPS: u64 = 100;
+
+// the bounty a crank get out of an escrow of amount , the buyer always keep at least 99% of it
+pub fn expire_crank_bounty(amount: u64) -> u64 {
+    let cap = (amount as u128 * EXPIRE_CRANK_BOUNTY_MAX_BPS as u128 / MAX_FEE_BPS as u128) as u64;
+    EXPIRE_CRANK_BOUNTY.min(cap)
+}
+
+/*
+    life of a deal (the deal account) :
+
+        BUY -> Pending --SHIP (seller)--> Shipped --SELL (buyer confirm)--> settled
+                  |                          |
+                  +--CANCEL (buyer)          +--CLAIM (seller , after confirm_deadline)--> settled
+                  +--EXPIRE (anyone , after accept_deadline)--> refunded
+        REJECT (seller) refund the buyer from Pending or Shipped
+        OPEN_DISPUTE (buyer or seller) move Pending or Shipped to Disputed , the escrow is frozen and only
+        RESOLVE_DISPUTE (arbiter) can pay it out
+    once shipped the buyer can't cancel any more , the money only goes to the seller.
+    a seller that never ship before accept_deadline can't SHIP any more , the deal can only be refunded.
+*/
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DealStatus {
+    Pending = 0, // money in escrow , the seller did not ship yet
+    Shipped = 1, // the seller handed the item over , waiting for the buyer
+    Disputed = 2, // frozen until the arbiter rule
+}
+
+impl DealStatus {
+    pub fn from_u8(value: u8) -> Result<Self, MarketplaceError> {
+        match value {
+            0 => Ok(Self::Pending),
+            1 => Ok(Self::Shipped),
+            2 => Ok(Self::Disputed),
+            _ => Err(MarketplaceError::InvalidAccountLayout),
+        }
+    }
+}
+
+impl DealData {
+    pub fn status(&self) -> Result<DealStatus, MarketplaceError> {
+        DealStatus::from_u8(self.status)
+    }
+
+    // who pay the registry rent when this deal settle , as the config said at BUY
+    pub fn rent_policy(&self) -> Result<RegistryRentPolicy, MarketplaceError> {
+        RegistryRentPolicy::from_u8(self.registry_rent_policy)
+    }
+
+    // for the handlers that need the deal in one status , a disputed deal get its own error so the frontend
+    // can tell the user the arbiter has it
+    pub fn check_status(&self, wanted: DealStatus) -> Result<(), MarketplaceError> {
+        let current = self.status()?;
+        if current == wanted {
+            return Ok(());
+        }
+        Err(match (current, wanted) {
+            (DealStatus::Disputed, _) => MarketplaceError::DealDisputed,
+            (_, DealStatus::Pending) => MarketplaceError::DealAlreadyShipped,
+            _ => MarketplaceError::DealNotShipped,
+        })
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Pod, Zeroable)]
+pub struct ImmutableRegistryData {
+    pub header: AccountHeader,
+    pub item_id: [u8; 32],
+    pub buyer: [u8; 32],
+    pub seller: [u8; 32],
+    pub price: u64,
+    pub title: [u8; 128],
+    pub description: [u8; 1024],
+    pub timestamp: u64,
+    pub fee: u64, // what the treasury took out of the escrow , the seller got the escrow minus this
+    pub bump: u8, // of ["IMUTABLE", item_id, buyer, seller]
+    pub _padding: [u8; 7],
+}
+
+impl ProgramAccount for ImmutableRegistryData {
+    const DISCRIMINATOR: [u8; 8] = *b"REGISTRY";
+    const VERSION: u8 = 3;
+}
+
+impl StoredBump for ImmutableRegistryData {
+    fn bump(&self) -> u8 {
+        self.bump
+    }
+}
+
+pub const MAX_ARBITERS: usize = 4;
+
+// the one marketplace config at ["CONFIG"] , created by INIT_CONFIG and changed by the admin with SET_CONFIG.
+// the admin hand over in two steps : PROPOSE_ADMIN write pending_admin , ACCEPT_ADMIN signed by it take over
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Pod, Zeroable)]
+pub struct ConfigData {
+    pub header: AccountHeader,
+    pub admin: [u8; 32],
+    pub pending_admin: [u8; 32], // all zero = no hand over in progress
+    pub fee_bps: u16, // taken from the seller payout of every SELL / CLAIM , locked per deal at BUY
+    pub pause_listings: u8,    // 1 = INIT and PUBLISH refused
+    pub pause_buys: u8,        // 1 = BUY refused
+    pub pause_settlements: u8, // 1 = SELL , CLAIM and a RESOLVE_DISPUTE paying the seller refused , refunds always go through
+    pub bump: u8,              // of ["CONFIG"]
+    pub registry_rent_policy: u8, // RegistryRentPolicy as u8 , who pay the registry rent on SELL / CLAIM
+    pub _padding: [u8; 1],
+    pub max_price: u64,      // INIT and UPDATE refuse a higher price , 0 = no limit
+    pub accept_window: i64,  // BUY set accept_deadline this far ahead
+    pub confirm_window: i64, // locked per deal at BUY , SHIP set confirm_deadline this far ahead
+    pub arbiters: [[u8; 32]; MAX_ARBITERS], // the only arbiters a listing can name , zero slots are unused
+}
+
+impl ProgramAccount for ConfigData {
+    const DISCRIMINATOR: [u8; 8] = *b"CONFIG__";
+    const VERSION: u8 = 5;
+}
+
+impl StoredBump for ConfigData {
+    fn bump(&self) -> u8 {
+        self.bump
+    }
+}
+
+impl ConfigData {
+    // a listing without arbiter is always fine , it just has no disputes
+    pub fn is_arbiter_allowed(&self, arbiter: &[u8; 32]) -> bool {
+        *arbiter == [0; 32] || self.arbiters.contains(arbiter)
+    }
+
+    pub fn check_price(&self, price: u64) -> Result<(), MarketplaceError> {
+        if self.max_price != 0 && price > self.max_price {
+            return Err(MarketplaceError::PriceAboveLimit);
+        }
+        Ok(())
+    }
+}
+
+pub const MAX_FEE_BPS: u16 = 10_000;
+
+// who pay the rent of the registry record SELL and CLAIM create. the share of a party that did not sign the
+// settlement can't be pulled from its wallet , it is taken from the sale proceeds instead (the seller payout)
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RegistryRentPolicy {
+    Settler = 0,  // whoever settle , the buyer on SELL and the seller on CLAIM. the default
+    Buyer = 1,
+    Seller = 2,
+    Split = 3,    // half each , the odd lamport on the seller
+    Treasury = 4, // the treasury sponsor it out of the collected fees
+    Proceeds = 5, // taken from the seller payout before it is paid
+}
+
+// the registry rent cut in the part each side pay
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RentShares {
+    pub buyer: u64,
+    pub seller: u64,
+    pub treasury: u64,
+    pub proceeds: u64,
+}
+
+impl RegistryRentPolicy {
+    pub fn from_u8(value: u8) -> Result<Self, MarketplaceError> {
+        match value {
+            0 => Ok(Self::Settler),
+            1 => Ok(Self::Buyer),
+            2 => Ok(Self::Seller),
+            3 => Ok(Self::Split),
+            4 => Ok(Self::Treasury),
+            5 => Ok(Self::Proceeds),
+            _ => Err(MarketplaceError::InvalidRentPolicy),
+        }
+    }
+
+    // on SELL only the buyer sign , so the seller share is taken from the proceeds. a token deal has no lamport
+    // proceeds , its seller would have to co-sign , so BUY refuse these policies for a token listing
+    pub fn charges_seller_on_sell(self) -> bool {
+        matches!(self, Self::Seller | Self::Split | Self::Proceeds)
+    }
+
+    // rent is what is still missing on the registry address , timeout_claim tell who settle
+    pub fn shares(self, rent: u64, timeout_claim: bool) -> RentShares {
+        let mut shares = RentShares::default();
+        match self {
+            Self::Settler if timeout_claim => shares.seller = rent,
+            Self::Settler | Self::Buyer => shares.buyer = rent,
+            Self::Seller => shares.seller = rent,
+            Self::Split => {
+                shares.buyer = rent / 2;
+                shares.seller = rent - rent / 2;
+            }
+            Self::Treasury => shares.treasury = rent,
+            Self::Proceeds => shares.proceeds = rent,
+        }
+        shares
+    }
+}
+
+// the treasury at ["TREASURY"] , its lamports above the rent reserve are the collected fees ,
+// token fees sit in its ATA of each mint
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Pod, Zeroable)]
+pub struct TreasuryData {
+    pub header: AccountHeader,
+    pub bump: u8, // of ["TREASURY"] , also sign the token withdrawals
+    pub _padding: [u8; 7],
+}
+
+impl ProgramAccount for TreasuryData {
+    const DISCRIMINATOR: [u8; 8] = *b"TREASURY";
+    const VERSION: u8 = 2;
+}
+
+impl StoredBump for TreasuryData {
+    fn bump(&self) -> u8 {
+        self.bump
+    }
+}
+
+// outcome of a dispute , written once by RESOLVE_DISPUTE at ["DISPUTE", deal_account] and never changed after
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Pod, Zeroable)]
+pub struct DisputeRecordData {
+    pub header: AccountHeader,
+    pub item_id: [u8; 32],
+    pub deal: [u8; 32],
+    pub buyer: [u8; 32],
+    pub seller: [u8; 32],
+    pub arbiter: [u8; 32],
+    pub buyer_evidence: [u8; 32],
+    pub seller_evidence: [u8; 32],
+    pub amount: u64,        // escrow that was in dispute
+    pub seller_amount: u64, // paid to the seller
+    pub buyer_amount: u64,  // refunded to the buyer
+    pub seller_bps: u16,    // the ruling , 0 = full refund , 10000 = full release
+    pub _padding: [u8; 6],
+    pub timestamp: u64,
+}
+
+impl ProgramAccount for DisputeRecordData {
+    const DISCRIMINATOR: [u8; 8] = *b"DISPUTE_";
+    const VERSION: u8 = 2;
+}
+
+// size check + header check , shared by load and load_mut
+fn check_header<T: ProgramAccount>(data: &[u8]) -> Result<(), MarketplaceError> {
+    if data.len() != std::mem::size_of::<T>() {
+        return Err(MarketplaceError::InvalidAccountLayout);
+    }
+    let header: AccountHeader =
+        bytemuck::pod_read_unaligned(&data[..std::mem::size_of::<AccountHeader>()]);
+    if header.discriminator != T::DISCRIMINATOR {
+        return Err(MarketplaceError::WrongAccountDiscriminator);
+    }
+    if header.version != T::VERSION {
+        return Err(MarketplaceError::UnsupportedAccountVersion);
+    }
+    Ok(())
+}
+
+// read an initialized account of type T
+pub fn load<T: ProgramAccount>(data: &[u8]) -> Result<&T, MarketplaceError> {
+    check_header::<T>(data)?;
+    bytemuck::try_from_bytes::<T>(data).map_err(|_| MarketplaceError::InvalidAccountLayout)
+}
+
+// same as load but for writing
+pub fn load_mut<T: ProgramAccount>(data: &mut [u8]) -> Result<&mut T, MarketplaceError> {
+    check_header::<T>(data)?;
+    bytemuck::try_from_bytes_mut::<T>(data).map_err(|_| MarketplaceError::InvalidAccountLayout)
+}
+
+// used right after create_account , the header must still be zero , we write it and hand back the struct
+pub fn init<T: ProgramAccount>(data: &mut [u8]) -> Result<&mut T, MarketplaceError> {
+    if data.len() != std::mem::size_of::<T>() {
+        return Err(MarketplaceError::InvalidAccountLayout);
+    }
+    if data[..std::mem::size_of::<AccountHeader>()].iter().any(|b| *b != 0) {
+        return Err(MarketplaceError::AccountAlreadyInitialized);
+    }
+    let header = AccountHeader {
+        discriminator: T::DISCRIMINATOR,
+        version: T::VERSION,
+        _padding: [0; 7],
+    };
+    data[..std::mem::size_of::<AccountHeader>()].copy_from_slice(bytemuck::bytes_of(&header));
+    bytemuck::try_from_bytes_mut::<T>(data).map_err(|_| MarketplaceError::InvalidAccountLayout)
+}
+
+// the one way a program owned account is closed. zeroing the lamports alone is not enough : until the end of the
+// transaction the account keep its owner and its size , so it could be funded again by a later instruction and
+// its data (zeroed or not) would still load as our struct. here every lamport goes to destination , the data is
+// zeroed and shrunk to nothing and the account is handed back to the system program. a closed account is just
+// that , zero length and system owned , so owned() and the size check refuse it as any live account
+pub fn close(account: &AccountInfo, destination: &AccountInfo) -> ProgramResult {
+    // closing into itself would burn the lamports
+    if account.key == destination.key {
+        return Err(MarketplaceError::LamportImbalance.into());
+    }
+    **destination.try_borrow_mut_lamports()? = destination
+        .lamports()
+        .checked_add(account.lamports())
+        .ok_or(MarketplaceError::ArithmeticOverflow)?;
+    **account.try_borrow_mut_lamports()? = 0;
+
+    account.try_borrow_mut_data()?.fill(0);
+    account.resize(0)?;
+    account.assign(&solana_system_interface::program::ID);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STATUSES: [ListingStatus; 5] = [
+        ListingStatus::Draft,
+        ListingStatus::Active,
+        ListingStatus::Reserved,
+        ListingStatus::Sold,
+        ListingStatus::Withdrawn,
+    ];
+
+    #[test]
+    fn listing_transitions() {
+        use ListingStatus::*;
+        let allowed = [
+            (Draft, Active),
+            (Active, Draft),
+            (Active, Reserved),
+            (Reserved, Active),
+            (Reserved, Sold),
+            (Draft, Withdrawn),
+            (Active, Withdrawn),
+            (Sold, Withdrawn),
+        ];
+        for from in STATUSES {
+            for to in STATUSES {
+                assert_eq!(
+                    from.can_become(to),
+                    allowed.contains(&(from, to)),
+                    "{:?} -> {:?}",
+                    from,
+                    to
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn reserved_listing_error() {
+        let mut listing = InitAccountData::zeroed();
+        listing.status = ListingStatus::Reserved as u8;
+        assert_eq!(
+            listing.set_status(ListingStatus::Withdrawn),
+            Err(MarketplaceError::ListingReserved)
+        );
+        listing.status = ListingStatus::Withdrawn as u8;
+        assert_eq!(
+            listing.set_status(ListingStatus::Active),
+            Err(MarketplaceError::IllegalListingTransition)
+        );
+    }
+
+    #[test]
+    fn rent_shares() {
+        use RegistryRentPolicy::*;
+        let rent = 1_001;
+        let shares = |buyer, seller, treasury, proceeds| RentShares {
+            buyer,
+            seller,
+            treasury,
+            proceeds,
+        };
+        assert_eq!(Settler.shares(rent, false), shares(rent, 0, 0, 0));
+        assert_eq!(Settler.shares(rent, true), shares(0, rent, 0, 0));
+        assert_eq!(Buyer.shares(rent, true), shares(rent, 0, 0, 0));
+        assert_eq!(Seller.shares(rent, false), shares(0, rent, 0, 0));
+        assert_eq!(Split.shares(rent, false), shares(500, 501, 0, 0));
+        assert_eq!(Treasury.shares(rent, false), shares(0, 0, rent, 0));
+        assert_eq!(Proceeds.shares(rent, true), shares(0, 0, 0, rent));
+
+        // the split always add up , nothing is charged when the rent is already there
+        for policy in [Settler, Buyer, Seller, Split, Treasury, Proceeds] {
+            for rent in [0, 1, 2, 890_880] {
+                let s = policy.shares(rent, false);
+                assert_eq!(s.buyer + s.seller + s.treasury + s.proceeds, rent);
+            }
+            // the seller is charged on SELL exactly when the shares put something on it
+            let s = policy.shares(1_001, false);
+            assert_eq!(policy.charges_seller_on_sell(), s.seller + s.proceeds > 0);
+        }
+    }
+
+    #[test]
+    fn deal_status_errors() {
+        let mut deal = DealData::zeroed();
+        let cases = [
+            (DealStatus::Pending, DealStatus::Pending, Ok(())),
+            (DealStatus::Shipped, DealStatus::Pending, Err(MarketplaceError::DealAlreadyShipped)),
+            (DealStatus::Disputed, DealStatus::Pending, Err(MarketplaceError::DealDisputed)),
+            (DealStatus::Shipped, DealStatus::Shipped, Ok(())),
+            (DealStatus::Pending, DealStatus::Shipped, Err(MarketplaceError::DealNotShipped)),
+            (DealStatus::Disputed, DealStatus::Shipped, Err(MarketplaceError::DealDisputed)),
+        ];
+        for (current, wanted, result) in cases {
+            deal.status = current as u8;
+            assert_eq!(deal.check_status(wanted), result, "{:?} wanted {:?}", current, wanted);
+        }
+    }
+
+    #[test]
+    fn crank_bounty() {
+        assert_eq!(expire_crank_bounty(0), 0);
+        assert_eq!(expire_crank_bounty(99), 0);
+        assert_eq!(expire_crank_bounty(100_000), 1_000);
+        assert_eq!(expire_crank_bounty(10_000_000), EXPIRE_CRANK_BOUNTY);
+        assert_eq!(expire_crank_bounty(u64::MAX), EXPIRE_CRANK_BOUNTY);
+    }
+
+    #[test]
+    fn rent_policy_values() {
+        for value in 0..=5 {
+            assert_eq!(RegistryRentPolicy::from_u8(value).map(|p| p as u8), Ok(value));
+        }
+        assert_eq!(
+            RegistryRentPolicy::from_u8(6),
+            Err(MarketplaceError::InvalidRentPolicy)
+        );
+    }
+}
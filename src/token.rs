@@ -0,0 +1,212 @@
+/*
+    the little part of the SPL Token , Token-2022 and Associated Token Account programs we need to hold a
+    listing payment in tokens. we don't pull the spl crates in , the instructions are a few bytes each so they are
+    encoded here by hand and the account layouts are read at their fixed offsets.
+
+    both token programs share the same base layouts :
+        mint          : 82 byte , decimals at offset 44
+        token account : 165 byte , [ {mint 32} , {owner 32} , {amount 8} , ... ]
+    Token-2022 accounts can be longer , after the base layout (padded to 165) come one account type byte and then the
+    extensions as [ {type u16} , {length u16} , {value} ] entries.
+*/
+
+use solana_program::{
+    account_info::AccountInfo,
+    instruction::{AccountMeta, Instruction},
+    pubkey,
+    pubkey::Pubkey,
+};
+
+use crate::error::MarketplaceError;
+
+pub const TOKEN_PROGRAM_ID: Pubkey = pubkey!("TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA");
+pub const TOKEN_2022_PROGRAM_ID: Pubkey = pubkey!("TokenzQdBNbLqP5VEhdkAS6EPFLC1PHnBqCXEpPxuEb");
+pub const ASSOCIATED_TOKEN_PROGRAM_ID: Pubkey =
+    pubkey!("ATokenGPvbdGVxr1b2hvZbsiqW5xWH25efTNsLJA8knL");
+
+const MINT_LEN: usize = 82;
+const MINT_DECIMALS_OFFSET: usize = 44;
+const ACCOUNT_LEN: usize = 165;
+const ACCOUNT_TYPE_MINT: u8 = 1;
+
+// Token-2022 extension types we look for on a mint
+const EXTENSION_TRANSFER_FEE_CONFIG: u16 = 1;
+const EXTENSION_PERMANENT_DELEGATE: u16 = 12;
+const EXTENSION_TRANSFER_HOOK: u16 = 14;
+
+// token instruction tags
+const TRANSFER_CHECKED: u8 = 12;
+const CLOSE_ACCOUNT: u8 = 9;
+const TRANSFER_FEE_EXTENSION: u8 = 26;
+const HARVEST_WITHHELD_TOKENS_TO_MINT: u8 = 4;
+// associated token account instruction tag
+const CREATE_IDEMPOTENT: u8 = 1;
+
+pub fn is_token_program(program_id: &Pubkey) -> bool {
+    *program_id == TOKEN_PROGRAM_ID || *program_id == TOKEN_2022_PROGRAM_ID
+}
+
+pub fn get_associated_token_address(wallet: &Pubkey, mint: &Pubkey, token_program: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(
+        &[wallet.as_ref(), token_program.as_ref(), mint.as_ref()],
+        &ASSOCIATED_TOKEN_PROGRAM_ID,
+    )
+    .0
+}
+
+// what a mint tell us about how its tokens move
+pub struct MintInfo {
+    pub decimals: u8,
+    pub transfer_fee: bool, // Token-2022 transfer fee , the receiver get less than what was sent
+}
+
+// read the mint , refuse the extensions that would let someone else move or block the escrowed tokens
+pub fn read_mint(mint: &AccountInfo) -> Result<MintInfo, MarketplaceError> {
+    if !is_token_program(mint.owner) {
+        return Err(MarketplaceError::WrongTokenProgram);
+    }
+    let data = mint.try_borrow_data().map_err(|_| MarketplaceError::InvalidTokenAccount)?;
+    if data.len() < MINT_LEN {
+        return Err(MarketplaceError::InvalidTokenAccount);
+    }
+    let mut info = MintInfo {
+        decimals: data[MINT_DECIMALS_OFFSET],
+        transfer_fee: false,
+    };
+    if data.len() > ACCOUNT_LEN {
+        if data[ACCOUNT_LEN] != ACCOUNT_TYPE_MINT {
+            return Err(MarketplaceError::InvalidTokenAccount);
+        }
+        for extension in extension_types(&data[ACCOUNT_LEN + 1..]) {
+            match extension {
+                EXTENSION_TRANSFER_FEE_CONFIG => info.transfer_fee = true,
+                // a permanent delegate can pull tokens out of the vault and a transfer hook need accounts we
+                // don't pass , so we don't take payments in these mints
+                EXTENSION_PERMANENT_DELEGATE | EXTENSION_TRANSFER_HOOK => {
+                    return Err(MarketplaceError::UnsupportedMint);
+                }
+                _ => {}
+            }
+        }
+    }
+    Ok(info)
+}
+
+// walk the [ {type} , {length} , {value} ] entries , stop at the first empty one
+fn extension_types(mut tlv: &[u8]) -> impl Iterator<Item = u16> + '_ {
+    std::iter::from_fn(move || {
+        if tlv.len() < 4 {
+            return None;
+        }
+        let kind = u16::from_le_bytes([tlv[0], tlv[1]]);
+        let len = u16::from_le_bytes([tlv[2], tlv[3]]) as usize;
+        if kind == 0 {
+            return None;
+        }
+        tlv = tlv.get(4 + len..).unwrap_or(&[]);
+        Some(kind)
+    })
+}
+
+// the base fields of a token account
+pub struct TokenAccountInfo {
+    pub mint: Pubkey,
+    pub owner: Pubkey,
+    pub amount: u64,
+}
+
+pub fn read_token_account(
+    account: &AccountInfo,
+    token_program: &Pubkey,
+) -> Result<TokenAccountInfo, MarketplaceError> {
+    if account.owner != token_program {
+        return Err(MarketplaceError::WrongTokenProgram);
+    }
+    let data = account.try_borrow_data().map_err(|_| MarketplaceError::InvalidTokenAccount)?;
+    if data.len() < ACCOUNT_LEN {
+        return Err(MarketplaceError::InvalidTokenAccount);
+    }
+    Ok(TokenAccountInfo {
+        mint: Pubkey::new_from_array(bytemuck::pod_read_unaligned(&data[0..32])),
+        owner: Pubkey::new_from_array(bytemuck::pod_read_unaligned(&data[32..64])),
+        amount: u64::from_le_bytes(bytemuck::pod_read_unaligned(&data[64..72])),
+    })
+}
+
+pub fn transfer_checked(
+    token_program: &Pubkey,
+    source: &Pubkey,
+    mint: &Pubkey,
+    destination: &Pubkey,
+    authority: &Pubkey,
+    amount: u64,
+    decimals: u8,
+) -> Instruction {
+    let mut data = Vec::with_capacity(10);
+    data.push(TRANSFER_CHECKED);
+    data.extend_from_slice(&amount.to_le_bytes());
+    data.push(decimals);
+    Instruction {
+        program_id: *token_program,
+        accounts: vec![
+            AccountMeta::new(*source, false),
+            AccountMeta::new_readonly(*mint, false),
+            AccountMeta::new(*destination, false),
+            AccountMeta::new_readonly(*authority, true),
+        ],
+        data,
+    }
+}
+
+pub fn close_account(
+    token_program: &Pubkey,
+    account: &Pubkey,
+    destination: &Pubkey,
+    owner: &Pubkey,
+) -> Instruction {
+    Instruction {
+        program_id: *token_program,
+        accounts: vec![
+            AccountMeta::new(*account, false),
+            AccountMeta::new(*destination, false),
+            AccountMeta::new_readonly(*owner, true),
+        ],
+        data: vec![CLOSE_ACCOUNT],
+    }
+}
+
+// Token-2022 keep the fee of every incoming transfer inside the receiving account and refuse to close it
+// until the fee is moved to the mint , anyone can do that
+pub fn harvest_withheld_tokens_to_mint(
+    token_program: &Pubkey,
+    mint: &Pubkey,
+    sources: &[&Pubkey],
+) -> Instruction {
+    let mut accounts = vec![AccountMeta::new(*mint, false)];
+    accounts.extend(sources.iter().map(|source| AccountMeta::new(**source, false)));
+    Instruction {
+        program_id: *token_program,
+        accounts,
+        data: vec![TRANSFER_FEE_EXTENSION, HARVEST_WITHHELD_TOKENS_TO_MINT],
+    }
+}
+
+pub fn create_associated_token_account_idempotent(
+    payer: &Pubkey,
+    wallet: &Pubkey,
+    mint: &Pubkey,
+    token_program: &Pubkey,
+) -> Instruction {
+    Instruction {
+        program_id: ASSOCIATED_TOKEN_PROGRAM_ID,
+        accounts: vec![
+            AccountMeta::new(*payer, true),
+            AccountMeta::new(get_associated_token_address(wallet, mint, token_program), false),
+            AccountMeta::new_readonly(*wallet, false),
+            AccountMeta::new_readonly(*mint, false),
+            AccountMeta::new_readonly(solana_system_interface::program::ID, false),
+            AccountMeta::new_readonly(*token_program, false),
+        ],
+        data: vec![CREATE_IDEMPOTENT],
+    }
+}
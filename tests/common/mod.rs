@@ -0,0 +1,962 @@
+/*
+    an in process runtime for the program tests , no validator and no SBF build needed.
+
+    Bank keep the accounts , process() serialize the accounts of an instruction the way the runtime lay them out
+    for a program (so AccountInfo::resize and the entrypoint deserialize work as on chain) , call
+    process_instruction and write the accounts back only if it returned Ok , like a failed transaction.
+    after every instruction it check the runtime rules a program can break : no lamport created or burnt ,
+    read only accounts untouched , only owned accounts debited or reassigned , nothing left below its rent.
+
+    the CPIs go through the syscall stubs of solana-program. the system program , the token programs (the
+    instructions token.rs build) and the associated token account program are emulated here , enough for the
+    accounts they really move. the clock and the rent come from the stubs too.
+*/
+#![allow(dead_code)] // each test file use its own part of this
+
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::sync::Once;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use bytemuck::Zeroable;
+use olx_contract::instruction::*;
+use olx_contract::state::{self, ConfigData, DealData, InitAccountData, ProgramAccount};
+use olx_contract::token::{self, ASSOCIATED_TOKEN_PROGRAM_ID, TOKEN_PROGRAM_ID};
+use solana_program::{
+    account_info::{AccountInfo, MAX_PERMITTED_DATA_INCREASE},
+    clock::Clock,
+    entrypoint::ProgramResult,
+    instruction::{AccountMeta, Instruction},
+    program_error::ProgramError,
+    program_stubs::{SyscallStubs, set_syscall_stubs},
+    pubkey::Pubkey,
+    rent::Rent,
+};
+
+pub const PROGRAM_ID: Pubkey = Pubkey::new_from_array([0x0d; 32]);
+pub const SYSTEM_PROGRAM_ID: Pubkey = solana_system_interface::program::ID;
+pub const BPF_LOADER_UPGRADEABLE_ID: Pubkey =
+    solana_program::pubkey!("BPFLoaderUpgradeab1e11111111111111111111111");
+pub const SOL: u64 = 1_000_000_000;
+pub const TOKEN_ACCOUNT_LEN: usize = 165;
+
+// a fresh key for every wallet , listing seed and mint of a test
+pub fn key() -> Pubkey {
+    static NEXT: AtomicU64 = AtomicU64::new(1);
+    let mut bytes = [0x5a; 32];
+    bytes[..8].copy_from_slice(&NEXT.fetch_add(1, Ordering::Relaxed).to_le_bytes());
+    Pubkey::new_from_array(bytes)
+}
+
+pub fn rent(len: usize) -> u64 {
+    Rent::default().minimum_balance(len)
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Account {
+    pub lamports: u64,
+    pub data: Vec<u8>,
+    pub owner: Pubkey,
+    pub executable: bool,
+}
+
+thread_local! {
+    static NOW: Cell<i64> = const { Cell::new(0) };
+    static CALLER: Cell<Pubkey> = const { Cell::new(Pubkey::new_from_array([0; 32])) };
+    // what the CPIs of the running instruction did , so the checks tell the program changes from the callee ones
+    static CPI_DELTAS: RefCell<HashMap<Pubkey, i128>> = RefCell::new(HashMap::new());
+    static CPI_TOUCHED: RefCell<HashSet<Pubkey>> = RefCell::new(HashSet::new());
+}
+
+pub struct Bank {
+    accounts: HashMap<Pubkey, Account>,
+    pub now: i64,
+}
+
+impl Bank {
+    pub fn new() -> Self {
+        static STUBS: Once = Once::new();
+        STUBS.call_once(|| {
+            set_syscall_stubs(Box::new(Stubs));
+        });
+        let mut bank = Bank {
+            accounts: HashMap::new(),
+            now: 1_700_000_000,
+        };
+        for program in [
+            PROGRAM_ID,
+            SYSTEM_PROGRAM_ID,
+            TOKEN_PROGRAM_ID,
+            token::TOKEN_2022_PROGRAM_ID,
+            ASSOCIATED_TOKEN_PROGRAM_ID,
+        ] {
+            bank.set(
+                program,
+                Account {
+                    lamports: 1,
+                    executable: true,
+                    ..Account::default()
+                },
+            );
+        }
+        bank
+    }
+
+    pub fn get(&self, key: &Pubkey) -> Account {
+        self.accounts.get(key).cloned().unwrap_or_default()
+    }
+
+    pub fn set(&mut self, key: Pubkey, account: Account) {
+        self.accounts.insert(key, account);
+    }
+
+    pub fn lamports(&self, key: &Pubkey) -> u64 {
+        self.get(key).lamports
+    }
+
+    pub fn exists(&self, key: &Pubkey) -> bool {
+        self.accounts.contains_key(key)
+    }
+
+    // a system wallet with lamports
+    pub fn wallet(&mut self, lamports: u64) -> Pubkey {
+        let wallet = key();
+        self.airdrop(&wallet, lamports);
+        wallet
+    }
+
+    // lamports sent to any address , what anybody can do to a PDA before the program create it
+    pub fn airdrop(&mut self, key: &Pubkey, lamports: u64) {
+        let account = self.accounts.entry(*key).or_default();
+        account.lamports += lamports;
+    }
+
+    pub fn load<T: ProgramAccount>(&self, key: &Pubkey) -> T {
+        let account = self.get(key);
+        assert_eq!(account.owner, PROGRAM_ID, "{} is not a program account", key);
+        let value: T = bytemuck::pod_read_unaligned(&account.data);
+        state::load::<T>(bytemuck::bytes_of(&value)).unwrap();
+        value
+    }
+
+    pub fn total_lamports(&self) -> u64 {
+        self.accounts.values().map(|account| account.lamports).sum()
+    }
+
+    // run one instruction as its own transaction
+    pub fn process(&mut self, ix: &Instruction) -> ProgramResult {
+        let (mut memory, slots) = self.serialize(ix);
+        let pre: Vec<Account> = slots.iter().map(|slot| self.get(&slot.key)).collect();
+
+        NOW.with(|now| now.set(self.now));
+        CALLER.with(|caller| caller.set(ix.program_id));
+        CPI_DELTAS.with(|deltas| deltas.borrow_mut().clear());
+        CPI_TOUCHED.with(|touched| touched.borrow_mut().clear());
+
+        {
+            let (program_id, infos, data) =
+                unsafe { solana_program::entrypoint::deserialize(memory.as_mut_ptr() as *mut u8) };
+            olx_contract::process_instruction(program_id, &infos, data)?;
+        }
+
+        let bytes: &[u8] = bytemuck::cast_slice(&memory);
+        let post: Vec<Account> = slots
+            .iter()
+            .zip(&pre)
+            .map(|(slot, pre)| {
+                let len = read_u64(bytes, slot.len_at) as usize;
+                Account {
+                    lamports: read_u64(bytes, slot.lamports_at),
+                    data: bytes[slot.data_at..slot.data_at + len].to_vec(),
+                    owner: Pubkey::new_from_array(bytes[slot.owner_at..slot.owner_at + 32].try_into().unwrap()),
+                    executable: pre.executable,
+                }
+            })
+            .collect();
+
+        check_runtime_rules(&ix.program_id, &slots, &pre, &post);
+
+        for (slot, account) in slots.iter().zip(post) {
+            // an account left with no lamports is gone at the end of the transaction
+            if account.lamports == 0 {
+                self.accounts.remove(&slot.key);
+            } else {
+                self.accounts.insert(slot.key, account);
+            }
+        }
+        Ok(())
+    }
+
+    // the input buffer of a program , as solana_program::entrypoint::deserialize read it. a repeated account
+    // point back to its first position , its signer and writable flags are the ones of all its metas
+    fn serialize(&self, ix: &Instruction) -> (Vec<u64>, Vec<Slot>) {
+        let mut buf = Vec::new();
+        let mut slots = Vec::new();
+        buf.extend_from_slice(&(ix.accounts.len() as u64).to_le_bytes());
+        for (i, meta) in ix.accounts.iter().enumerate() {
+            if let Some(first) = ix.accounts[..i].iter().position(|m| m.pubkey == meta.pubkey) {
+                buf.push(first as u8);
+                buf.extend_from_slice(&[0; 7]);
+                continue;
+            }
+            let same = || ix.accounts.iter().filter(|m| m.pubkey == meta.pubkey);
+            let signer = same().any(|m| m.is_signer);
+            let writable = same().any(|m| m.is_writable);
+            let account = self.get(&meta.pubkey);
+
+            buf.extend_from_slice(&[0xff, signer as u8, writable as u8, account.executable as u8, 0, 0, 0, 0]);
+            buf.extend_from_slice(meta.pubkey.as_ref());
+            let owner_at = buf.len();
+            buf.extend_from_slice(account.owner.as_ref());
+            let lamports_at = buf.len();
+            buf.extend_from_slice(&account.lamports.to_le_bytes());
+            let len_at = buf.len();
+            buf.extend_from_slice(&(account.data.len() as u64).to_le_bytes());
+            let data_at = buf.len();
+            buf.extend_from_slice(&account.data);
+            buf.resize(buf.len() + MAX_PERMITTED_DATA_INCREASE + 8, 0); // room to grow , then the rent epoch
+            buf.resize(buf.len().next_multiple_of(8), 0);
+            slots.push(Slot {
+                key: meta.pubkey,
+                writable,
+                owner_at,
+                lamports_at,
+                len_at,
+                data_at,
+            });
+        }
+        buf.extend_from_slice(&(ix.data.len() as u64).to_le_bytes());
+        buf.extend_from_slice(&ix.data);
+        buf.extend_from_slice(ix.program_id.as_ref());
+
+        // the runtime hand the program an 8 byte aligned buffer
+        let mut memory = vec![0u64; buf.len().div_ceil(8)];
+        bytemuck::cast_slice_mut::<u64, u8>(&mut memory)[..buf.len()].copy_from_slice(&buf);
+        (memory, slots)
+    }
+}
+
+impl Default for Bank {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+struct Slot {
+    key: Pubkey,
+    writable: bool,
+    owner_at: usize,
+    lamports_at: usize,
+    len_at: usize,
+    data_at: usize,
+}
+
+fn read_u64(bytes: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap())
+}
+
+// what the runtime refuse at the end of an instruction. a program that break one of these has a bug the
+// validator would turn into a failed transaction , so the test stop right here
+fn check_runtime_rules(program_id: &Pubkey, slots: &[Slot], pre: &[Account], post: &[Account]) {
+    let before: u128 = pre.iter().map(|account| account.lamports as u128).sum();
+    let after: u128 = post.iter().map(|account| account.lamports as u128).sum();
+    assert_eq!(before, after, "lamports were created or burnt");
+
+    let deltas = CPI_DELTAS.with(|deltas| deltas.borrow().clone());
+    let touched = CPI_TOUCHED.with(|touched| touched.borrow().clone());
+    for ((slot, pre), post) in slots.iter().zip(pre).zip(post) {
+        if pre == post {
+            continue;
+        }
+        assert!(slot.writable, "read only account {} was changed", slot.key);
+
+        let by_program = post.lamports as i128 - pre.lamports as i128 - deltas.get(&slot.key).unwrap_or(&0);
+        assert!(
+            by_program >= 0 || pre.owner == *program_id,
+            "{} was debited by a program that does not own it",
+            slot.key
+        );
+        if pre.owner != post.owner && !touched.contains(&slot.key) {
+            assert_eq!(pre.owner, *program_id, "{} was reassigned by a program that does not own it", slot.key);
+            assert!(post.data.iter().all(|b| *b == 0), "{} was reassigned with data left in it", slot.key);
+        }
+        if pre.data != post.data && !touched.contains(&slot.key) {
+            assert_eq!(pre.owner, *program_id, "{} data was written by a program that does not own it", slot.key);
+        }
+        if post.lamports > 0 {
+            assert!(
+                post.lamports >= rent(post.data.len()),
+                "{} was left below its rent exemption ({} lamports for {} bytes)",
+                slot.key,
+                post.lamports,
+                post.data.len()
+            );
+        }
+    }
+}
+
+struct Stubs;
+
+impl SyscallStubs for Stubs {
+    fn sol_get_rent_sysvar(&self, var_addr: *mut u8) -> u64 {
+        unsafe { *(var_addr as *mut Rent) = Rent::default() };
+        0
+    }
+
+    fn sol_get_clock_sysvar(&self, var_addr: *mut u8) -> u64 {
+        let clock = Clock {
+            unix_timestamp: NOW.with(|now| now.get()),
+            ..Clock::default()
+        };
+        unsafe { *(var_addr as *mut Clock) = clock };
+        0
+    }
+
+    fn sol_invoke_signed(
+        &self,
+        ix: &Instruction,
+        account_infos: &[AccountInfo],
+        signers_seeds: &[&[&[u8]]],
+    ) -> ProgramResult {
+        let caller = CALLER.with(|caller| caller.get());
+        let mut pdas = Vec::new();
+        for seeds in signers_seeds {
+            pdas.push(Pubkey::create_program_address(seeds, &caller).map_err(|_| ProgramError::InvalidSeeds)?);
+        }
+        if !account_infos.iter().any(|info| *info.key == ix.program_id) {
+            println!("runtime: program {} was not passed to the CPI", ix.program_id);
+            return Err(ProgramError::NotEnoughAccountKeys);
+        }
+
+        let mut accounts = Vec::new();
+        for meta in &ix.accounts {
+            let info = account_infos
+                .iter()
+                .find(|info| *info.key == meta.pubkey)
+                .ok_or(ProgramError::NotEnoughAccountKeys)?;
+            if meta.is_signer && !info.is_signer && !pdas.contains(info.key) {
+                println!("runtime: {} signs the CPI but is no signer", info.key);
+                return Err(ProgramError::MissingRequiredSignature);
+            }
+            if meta.is_writable && !info.is_writable {
+                println!("runtime: {} is written by the CPI but is read only", info.key);
+                return Err(ProgramError::InvalidArgument);
+            }
+            accounts.push(Callee {
+                info: info.clone(),
+                signer: meta.is_signer,
+            });
+        }
+
+        let before: Vec<u64> = accounts.iter().map(|account| account.info.lamports()).collect();
+        let result = match ix.program_id {
+            id if id == SYSTEM_PROGRAM_ID => system_program(&ix.data, &accounts),
+            id if token::is_token_program(&id) => token_program(&id, &ix.data, &accounts),
+            id if id == ASSOCIATED_TOKEN_PROGRAM_ID => associated_token_program(&ix.data, &accounts),
+            id => panic!("no emulation of program {}", id),
+        };
+
+        let mut seen = HashSet::new();
+        for (account, before) in accounts.iter().zip(before) {
+            if !seen.insert(*account.info.key) {
+                continue;
+            }
+            CPI_DELTAS.with(|deltas| {
+                *deltas.borrow_mut().entry(*account.info.key).or_default() +=
+                    account.info.lamports() as i128 - before as i128;
+            });
+            if account.info.is_writable {
+                CPI_TOUCHED.with(|touched| touched.borrow_mut().insert(*account.info.key));
+            }
+        }
+        if let Err(e) = &result {
+            println!("runtime: CPI to {} failed with {:?}", ix.program_id, e);
+        }
+        result
+    }
+}
+
+// an account as the called program see it , signer if the caller signed for it (wallet or PDA seeds)
+struct Callee<'a> {
+    info: AccountInfo<'a>,
+    signer: bool,
+}
+
+fn move_lamports(from: &AccountInfo, to: &AccountInfo, lamports: u64) -> ProgramResult {
+    if from.lamports() < lamports {
+        return Err(ProgramError::Custom(1)); // SystemError::ResultWithNegativeLamports
+    }
+    **from.try_borrow_mut_lamports()? -= lamports;
+    **to.try_borrow_mut_lamports()? += lamports;
+    Ok(())
+}
+
+fn system_program(data: &[u8], accounts: &[Callee]) -> ProgramResult {
+    let kind = u32::from_le_bytes(data[..4].try_into().unwrap());
+    let u64_at = |at: usize| u64::from_le_bytes(data[at..at + 8].try_into().unwrap());
+    let require_signer = |account: &Callee| {
+        if account.signer {
+            Ok(())
+        } else {
+            Err(ProgramError::MissingRequiredSignature)
+        }
+    };
+    // allocate only take a fresh system account , with or without lamports
+    let require_unused = |account: &Callee| {
+        if account.info.data_is_empty() && *account.info.owner == SYSTEM_PROGRAM_ID {
+            Ok(())
+        } else {
+            Err(ProgramError::Custom(0)) // SystemError::AccountAlreadyInUse
+        }
+    };
+    let transfer = |from: &Callee, to: &Callee, lamports: u64| {
+        require_signer(from)?;
+        if !from.info.data_is_empty() || *from.info.owner != SYSTEM_PROGRAM_ID {
+            return Err(ProgramError::InvalidArgument); // the source of a transfer must not carry data
+        }
+        move_lamports(&from.info, &to.info, lamports)
+    };
+    match kind {
+        // CreateAccount { lamports , space , owner }
+        0 => {
+            let (from, to) = (&accounts[0], &accounts[1]);
+            require_signer(to)?;
+            if to.info.lamports() > 0 {
+                return Err(ProgramError::Custom(0));
+            }
+            require_unused(to)?;
+            transfer(from, to, u64_at(4))?;
+            to.info.resize(u64_at(12) as usize)?;
+            to.info.assign(&Pubkey::new_from_array(data[20..52].try_into().unwrap()));
+            Ok(())
+        }
+        // Assign { owner }
+        1 => {
+            let account = &accounts[0];
+            require_signer(account)?;
+            if *account.info.owner != SYSTEM_PROGRAM_ID {
+                return Err(ProgramError::Custom(0));
+            }
+            account.info.assign(&Pubkey::new_from_array(data[4..36].try_into().unwrap()));
+            Ok(())
+        }
+        // Transfer { lamports }
+        2 => transfer(&accounts[0], &accounts[1], u64_at(4)),
+        // Allocate { space }
+        8 => {
+            let account = &accounts[0];
+            require_signer(account)?;
+            require_unused(account)?;
+            account.info.resize(u64_at(4) as usize)
+        }
+        _ => panic!("no emulation of system instruction {}", kind),
+    }
+}
+
+// the base token account fields token.rs read
+fn token_fields(info: &AccountInfo) -> (Pubkey, Pubkey, u64) {
+    let data = info.data.borrow();
+    (
+        Pubkey::new_from_array(data[0..32].try_into().unwrap()),
+        Pubkey::new_from_array(data[32..64].try_into().unwrap()),
+        u64::from_le_bytes(data[64..72].try_into().unwrap()),
+    )
+}
+
+fn set_token_amount(info: &AccountInfo, amount: u64) {
+    info.data.borrow_mut()[64..72].copy_from_slice(&amount.to_le_bytes());
+}
+
+fn token_program(program_id: &Pubkey, data: &[u8], accounts: &[Callee]) -> ProgramResult {
+    // the token accounts and the mint must belong to the program called
+    let token_account = |account: &Callee| {
+        if account.info.owner == program_id && account.info.data_len() >= TOKEN_ACCOUNT_LEN {
+            Ok(())
+        } else {
+            Err(ProgramError::IncorrectProgramId)
+        }
+    };
+    match data[0] {
+        // TransferChecked { amount , decimals } : source , mint , destination , authority
+        12 => {
+            let (source, mint, destination, authority) =
+                (&accounts[0], &accounts[1], &accounts[2], &accounts[3]);
+            token_account(source)?;
+            token_account(destination)?;
+            if mint.info.owner != program_id {
+                return Err(ProgramError::IncorrectProgramId);
+            }
+            let amount = u64::from_le_bytes(data[1..9].try_into().unwrap());
+            let (source_mint, source_owner, source_amount) = token_fields(&source.info);
+            let (destination_mint, _, destination_amount) = token_fields(&destination.info);
+            if source_mint != *mint.info.key || destination_mint != *mint.info.key {
+                return Err(ProgramError::Custom(3)); // TokenError::MintMismatch
+            }
+            if source_owner != *authority.info.key || !authority.signer {
+                return Err(ProgramError::Custom(4)); // TokenError::OwnerMismatch
+            }
+            if data[9] != mint.info.data.borrow()[44] {
+                return Err(ProgramError::Custom(18)); // TokenError::MintDecimalsMismatch
+            }
+            if amount > source_amount {
+                return Err(ProgramError::Custom(1)); // TokenError::InsufficientFunds
+            }
+            if source.info.key != destination.info.key {
+                set_token_amount(&source.info, source_amount - amount);
+                set_token_amount(&destination.info, destination_amount + amount);
+            }
+            Ok(())
+        }
+        // CloseAccount : account , destination , owner
+        9 => {
+            let (account, destination, owner) = (&accounts[0], &accounts[1], &accounts[2]);
+            token_account(account)?;
+            let (_, account_owner, amount) = token_fields(&account.info);
+            if account_owner != *owner.info.key || !owner.signer {
+                return Err(ProgramError::Custom(4));
+            }
+            if amount != 0 {
+                return Err(ProgramError::Custom(11)); // TokenError::NonNativeHasBalance
+            }
+            move_lamports(&account.info, &destination.info, account.info.lamports())?;
+            account.info.data.borrow_mut().fill(0);
+            Ok(())
+        }
+        tag => panic!("no emulation of token instruction {}", tag),
+    }
+}
+
+// CreateIdempotent : payer , ata , wallet , mint , system program , token program
+fn associated_token_program(data: &[u8], accounts: &[Callee]) -> ProgramResult {
+    assert_eq!(data, [1], "no emulation of this associated token instruction");
+    let (payer, ata, wallet, mint, token_program) =
+        (&accounts[0], &accounts[1], &accounts[2], &accounts[3], &accounts[5]);
+    let address = token::get_associated_token_address(wallet.info.key, mint.info.key, token_program.info.key);
+    if *ata.info.key != address {
+        return Err(ProgramError::InvalidSeeds);
+    }
+    if ata.info.owner == token_program.info.key {
+        let (ata_mint, ata_owner, _) = token_fields(&ata.info);
+        if ata_mint != *mint.info.key || ata_owner != *wallet.info.key {
+            return Err(ProgramError::IllegalOwner);
+        }
+        return Ok(());
+    }
+    if !payer.signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    if !ata.info.data_is_empty() || *ata.info.owner != SYSTEM_PROGRAM_ID {
+        return Err(ProgramError::Custom(0));
+    }
+    // like the real program , lamports already at the address count toward the rent
+    let missing = rent(TOKEN_ACCOUNT_LEN).saturating_sub(ata.info.lamports());
+    move_lamports(&payer.info, &ata.info, missing)?;
+    ata.info.resize(TOKEN_ACCOUNT_LEN)?;
+    ata.info.assign(token_program.info.key);
+    ata.info.data.borrow_mut()[..TOKEN_ACCOUNT_LEN].copy_from_slice(&token_account_data(
+        mint.info.key,
+        wallet.info.key,
+        0,
+    ));
+    Ok(())
+}
+
+pub fn token_account_data(mint: &Pubkey, owner: &Pubkey, amount: u64) -> Vec<u8> {
+    let mut data = vec![0; TOKEN_ACCOUNT_LEN];
+    data[0..32].copy_from_slice(mint.as_ref());
+    data[32..64].copy_from_slice(owner.as_ref());
+    data[64..72].copy_from_slice(&amount.to_le_bytes());
+    data[108] = 1; // AccountState::Initialized
+    data
+}
+
+impl Bank {
+    // an SPL Token mint with no extensions
+    pub fn mint(&mut self, decimals: u8) -> Pubkey {
+        let mint = key();
+        let mut data = vec![0; 82];
+        data[44] = decimals;
+        data[45] = 1; // is_initialized
+        self.set(
+            mint,
+            Account {
+                lamports: rent(82),
+                data,
+                owner: TOKEN_PROGRAM_ID,
+                executable: false,
+            },
+        );
+        mint
+    }
+
+    // the ATA of wallet for mint , holding amount
+    pub fn token_account(&mut self, wallet: &Pubkey, mint: &Pubkey, amount: u64) -> Pubkey {
+        let address = token::get_associated_token_address(wallet, mint, &TOKEN_PROGRAM_ID);
+        self.set(
+            address,
+            Account {
+                lamports: rent(TOKEN_ACCOUNT_LEN),
+                data: token_account_data(mint, wallet, amount),
+                owner: TOKEN_PROGRAM_ID,
+                executable: false,
+            },
+        );
+        address
+    }
+
+    pub fn token_balance(&self, token_account: &Pubkey) -> u64 {
+        let data = self.get(token_account).data;
+        u64::from_le_bytes(data[64..72].try_into().unwrap())
+    }
+}
+
+pub fn ata(wallet: &Pubkey, mint: &Pubkey) -> Pubkey {
+    token::get_associated_token_address(wallet, mint, &TOKEN_PROGRAM_ID)
+}
+
+fn pda(seeds: &[&[u8]]) -> Pubkey {
+    Pubkey::find_program_address(seeds, &PROGRAM_ID).0
+}
+
+pub fn config_address() -> Pubkey {
+    pda(&[b"CONFIG"])
+}
+
+pub fn treasury_address() -> Pubkey {
+    pda(&[b"TREASURY"])
+}
+
+pub fn program_data_address() -> Pubkey {
+    Pubkey::find_program_address(&[PROGRAM_ID.as_ref()], &BPF_LOADER_UPGRADEABLE_ID).0
+}
+
+pub fn listing_address(seed: &[u8; 32], owner: &Pubkey) -> Pubkey {
+    pda(&[b"INIT", seed, owner.as_ref()])
+}
+
+pub fn deal_address(seed: &[u8; 32], buyer: &Pubkey) -> Pubkey {
+    pda(&[b"DEAL", seed, buyer.as_ref()])
+}
+
+pub fn registry_address(item_id: &[u8; 32], buyer: &Pubkey, seller: &Pubkey) -> Pubkey {
+    pda(&[b"IMUTABLE", item_id, buyer.as_ref(), seller.as_ref()])
+}
+
+pub fn dispute_address(deal: &Pubkey) -> Pubkey {
+    pda(&[b"DISPUTE", deal.as_ref()])
+}
+
+fn ix(accounts: Vec<AccountMeta>, data: MarketplaceInstruction) -> Instruction {
+    Instruction {
+        program_id: PROGRAM_ID,
+        accounts,
+        data: data.pack(),
+    }
+}
+
+fn w(key: &Pubkey) -> AccountMeta {
+    AccountMeta::new(*key, false)
+}
+
+fn r(key: &Pubkey) -> AccountMeta {
+    AccountMeta::new_readonly(*key, false)
+}
+
+fn s(key: &Pubkey) -> AccountMeta {
+    AccountMeta::new(*key, true)
+}
+
+// a listing the tests made , what the deal instructions need to know of it
+#[derive(Clone, Copy, Debug)]
+pub struct Listing {
+    pub key: Pubkey,
+    pub seller: Pubkey,
+    pub seed: [u8; 32],
+    pub item_id: [u8; 32],
+    pub price: u64,
+    pub mint: Option<Pubkey>,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Deal {
+    pub key: Pubkey,
+    pub listing: Listing,
+    pub buyer: Pubkey,
+    pub seller: Pubkey,
+}
+
+impl Deal {
+    pub fn vault(&self) -> Option<Pubkey> {
+        self.listing.mint.map(|mint| ata(&self.key, &mint))
+    }
+
+    // [mint , vault , token_program] then the token account of each wallet , nothing for a lamport deal
+    pub fn token_accounts(&self, wallets: &[&Pubkey]) -> Vec<AccountMeta> {
+        let Some(mint) = self.listing.mint else {
+            return Vec::new();
+        };
+        let mut metas = vec![r(&mint), w(&ata(&self.key, &mint)), r(&TOKEN_PROGRAM_ID)];
+        metas.extend(wallets.iter().map(|wallet| w(&ata(wallet, &mint))));
+        metas
+    }
+}
+
+pub fn init_config_ix(admin: &Pubkey, fee_bps: u16) -> Instruction {
+    ix(
+        vec![
+            s(admin),
+            w(&config_address()),
+            w(&treasury_address()),
+            r(&program_data_address()),
+            r(&SYSTEM_PROGRAM_ID),
+        ],
+        MarketplaceInstruction::InitConfig(InitConfigData { fee_bps }),
+    )
+}
+
+pub fn set_config_ix(admin: &Pubkey, data: SetConfigData) -> Instruction {
+    ix(vec![s(admin), w(&config_address())], MarketplaceInstruction::SetConfig(data))
+}
+
+pub fn set_pause_ix(admin: &Pubkey, data: SetPauseData) -> Instruction {
+    ix(vec![s(admin), w(&config_address())], MarketplaceInstruction::SetPause(data))
+}
+
+pub fn init_ix(owner: &Pubkey, listing: &Listing, arbiter: &Pubkey) -> Instruction {
+    let mut data = InitData::zeroed();
+    data.item_id = listing.item_id;
+    data.price = listing.price;
+    data.seed = listing.seed;
+    data.arbiter = arbiter.to_bytes();
+    data.payment_mint = listing.mint.map(|mint| mint.to_bytes()).unwrap_or_default();
+    ix(
+        vec![s(owner), w(&listing.key), r(&SYSTEM_PROGRAM_ID), r(&config_address())],
+        MarketplaceInstruction::Init(data),
+    )
+}
+
+pub fn publish_ix(owner: &Pubkey, listing: &Listing) -> Instruction {
+    ix(
+        vec![s(owner), w(&listing.key), r(&config_address())],
+        MarketplaceInstruction::Publish(PublishData { seed: listing.seed }),
+    )
+}
+
+pub fn buy_ix(buyer: &Pubkey, listing: &Listing, seed: &[u8; 32], revision: u32) -> Instruction {
+    let mut data = BuyInit::zeroed();
+    data.item_id = listing.item_id;
+    data.buyer = buyer.to_bytes();
+    data.seed = *seed;
+    data.max_price = listing.price;
+    data.expected_revision = revision;
+    let deal = deal_address(seed, buyer);
+    let mut accounts = vec![s(buyer), w(&listing.key), w(&deal), r(&SYSTEM_PROGRAM_ID), r(&config_address())];
+    if let Some(mint) = listing.mint {
+        accounts.extend([
+            r(&mint),
+            w(&ata(buyer, &mint)),
+            w(&ata(&deal, &mint)),
+            r(&TOKEN_PROGRAM_ID),
+            r(&ASSOCIATED_TOKEN_PROGRAM_ID),
+        ]);
+    }
+    ix(accounts, MarketplaceInstruction::Buy(data))
+}
+
+pub fn ship_ix(deal: &Deal) -> Instruction {
+    ix(vec![s(&deal.seller), w(&deal.key)], MarketplaceInstruction::Ship)
+}
+
+// SELL signed by the buyer , CLAIM signed by the seller
+pub fn settle_ix(deal: &Deal, timeout_claim: bool) -> Instruction {
+    let (seller, buyer) = if timeout_claim {
+        (s(&deal.seller), w(&deal.buyer))
+    } else {
+        (w(&deal.seller), s(&deal.buyer))
+    };
+    let mut accounts = vec![
+        seller,
+        buyer,
+        w(&deal.key),
+        w(&deal.listing.key),
+        r(&SYSTEM_PROGRAM_ID),
+        w(&registry_address(&deal.listing.item_id, &deal.buyer, &deal.seller)),
+        w(&treasury_address()),
+        r(&config_address()),
+    ];
+    accounts.extend(deal.token_accounts(&[&deal.seller]));
+    if let Some(mint) = deal.listing.mint {
+        accounts.extend([w(&ata(&treasury_address(), &mint)), r(&ASSOCIATED_TOKEN_PROGRAM_ID)]);
+    }
+    let data = if timeout_claim {
+        MarketplaceInstruction::Claim
+    } else {
+        MarketplaceInstruction::Sell
+    };
+    ix(accounts, data)
+}
+
+pub fn cancel_ix(deal: &Deal) -> Instruction {
+    let mut accounts = vec![s(&deal.buyer), w(&deal.key), w(&deal.listing.key)];
+    accounts.extend(deal.token_accounts(&[&deal.buyer]));
+    ix(accounts, MarketplaceInstruction::Cancel)
+}
+
+pub fn expire_ix(deal: &Deal, caller: &Pubkey) -> Instruction {
+    let mut accounts = vec![s(caller), w(&deal.buyer), w(&deal.key), w(&deal.listing.key)];
+    accounts.extend(deal.token_accounts(&[&deal.buyer]));
+    ix(accounts, MarketplaceInstruction::Expire)
+}
+
+pub fn reject_ix(deal: &Deal) -> Instruction {
+    let mut accounts = vec![s(&deal.seller), w(&deal.buyer), w(&deal.key), w(&deal.listing.key)];
+    accounts.extend(deal.token_accounts(&[&deal.buyer]));
+    ix(accounts, MarketplaceInstruction::Reject(RejectData { reason: 0 }))
+}
+
+pub fn open_dispute_ix(deal: &Deal, party: &Pubkey) -> Instruction {
+    ix(
+        vec![s(party), w(&deal.key)],
+        MarketplaceInstruction::OpenDispute(EvidenceData { evidence: [1; 32] }),
+    )
+}
+
+pub fn resolve_ix(deal: &Deal, arbiter: &Pubkey, seller_bps: u16) -> Instruction {
+    let mut accounts = vec![
+        s(arbiter),
+        w(&deal.seller),
+        w(&deal.buyer),
+        w(&deal.key),
+        w(&deal.listing.key),
+        w(&dispute_address(&deal.key)),
+        r(&SYSTEM_PROGRAM_ID),
+        r(&config_address()),
+    ];
+    accounts.extend(deal.token_accounts(&[&deal.seller, &deal.buyer]));
+    ix(accounts, MarketplaceInstruction::ResolveDispute(ResolveData { seller_bps }))
+}
+
+// a marketplace whose config and treasury exist , with the upgrade authority as admin
+pub struct Market {
+    pub bank: Bank,
+    pub admin: Pubkey,
+}
+
+impl Market {
+    pub fn new(fee_bps: u16) -> Self {
+        let mut market = Self::uninitialized();
+        let admin = market.admin;
+        market.bank.process(&init_config_ix(&admin, fee_bps)).unwrap();
+        market
+    }
+
+    // the program is deployed but INIT_CONFIG was not sent yet
+    pub fn uninitialized() -> Self {
+        let mut bank = Bank::new();
+        let admin = bank.wallet(10 * SOL);
+        // ProgramData : [ {tag u32 = 3} , {slot u64} , {Some} , {authority} ]
+        let mut data = vec![0; 45];
+        data[0..4].copy_from_slice(&3u32.to_le_bytes());
+        data[12] = 1;
+        data[13..45].copy_from_slice(admin.as_ref());
+        bank.set(
+            program_data_address(),
+            Account {
+                lamports: rent(45),
+                data,
+                owner: BPF_LOADER_UPGRADEABLE_ID,
+                executable: false,
+            },
+        );
+        Market { bank, admin }
+    }
+
+    pub fn config(&self) -> ConfigData {
+        self.bank.load(&config_address())
+    }
+
+    // the current config with a few fields changed
+    pub fn set_config(&mut self, change: impl FnOnce(&mut SetConfigData)) {
+        let config = self.config();
+        let mut data = SetConfigData::zeroed();
+        data.fee_bps = config.fee_bps;
+        data.registry_rent_policy = config.registry_rent_policy;
+        data.max_price = config.max_price;
+        data.accept_window = config.accept_window;
+        data.confirm_window = config.confirm_window;
+        data.arbiters = config.arbiters;
+        change(&mut data);
+        let admin = self.admin;
+        self.bank.process(&set_config_ix(&admin, data)).unwrap();
+    }
+
+    // a published listing , priced in lamports or in mint
+    pub fn listing(&mut self, seller: &Pubkey, price: u64, mint: Option<Pubkey>) -> Listing {
+        self.listing_with_arbiter(seller, price, mint, &Pubkey::default())
+    }
+
+    pub fn listing_with_arbiter(
+        &mut self,
+        seller: &Pubkey,
+        price: u64,
+        mint: Option<Pubkey>,
+        arbiter: &Pubkey,
+    ) -> Listing {
+        let seed = key().to_bytes();
+        let listing = Listing {
+            key: listing_address(&seed, seller),
+            seller: *seller,
+            seed,
+            item_id: key().to_bytes(),
+            price,
+            mint,
+        };
+        self.bank.process(&init_ix(seller, &listing, arbiter)).unwrap();
+        self.bank.process(&publish_ix(seller, &listing)).unwrap();
+        listing
+    }
+
+    pub fn buy_ix(&self, buyer: &Pubkey, listing: &Listing) -> (Instruction, Deal) {
+        let seed = key().to_bytes();
+        let revision = self.bank.load::<InitAccountData>(&listing.key).revision;
+        let deal = Deal {
+            key: deal_address(&seed, buyer),
+            listing: *listing,
+            buyer: *buyer,
+            seller: listing.seller,
+        };
+        (buy_ix(buyer, listing, &seed, revision), deal)
+    }
+
+    pub fn buy(&mut self, buyer: &Pubkey, listing: &Listing) -> Deal {
+        let (ix, deal) = self.buy_ix(buyer, listing);
+        self.bank.process(&ix).unwrap();
+        deal
+    }
+
+    // bought and shipped , ready for SELL
+    pub fn shipped(&mut self, buyer: &Pubkey, listing: &Listing) -> Deal {
+        let deal = self.buy(buyer, listing);
+        self.bank.process(&ship_ix(&deal)).unwrap();
+        deal
+    }
+
+    pub fn deal(&self, deal: &Deal) -> DealData {
+        self.bank.load(&deal.key)
+    }
+}
+
+// the custom error code of a MarketplaceError , to compare with what process returned
+pub fn error(error: olx_contract::error::MarketplaceError) -> ProgramError {
+    error.into()
+}
+
+pub fn with_signer(mut ix: Instruction, signer: &Pubkey) -> Instruction {
+    ix.accounts.push(s(signer));
+    ix
+}
+
+pub fn with_account(mut ix: Instruction, account: &Pubkey) -> Instruction {
+    ix.accounts.push(w(account));
+    ix
+}
@@ -0,0 +1,53 @@
+mod common;
+
+use common::*;
+use olx_contract::error::MarketplaceError;
+use olx_contract::state::{ConfigData, TreasuryData};
+
+#[test]
+fn init_config_creates_config_and_treasury() {
+    let mut market = Market::uninitialized();
+    let admin = market.admin;
+    market.bank.process(&init_config_ix(&admin, 300)).unwrap();
+
+    let config = market.config();
+    assert_eq!(config.admin, admin.to_bytes());
+    assert_eq!(config.fee_bps, 300);
+    market.bank.load::<TreasuryData>(&treasury_address());
+    let rents = rent(std::mem::size_of::<ConfigData>()) + rent(std::mem::size_of::<TreasuryData>());
+    assert_eq!(market.bank.lamports(&admin), 10 * SOL - rents);
+
+    // once only
+    assert!(market.bank.process(&init_config_ix(&admin, 0)).is_err());
+    assert_eq!(market.config().fee_bps, 300);
+}
+
+#[test]
+fn init_config_only_by_the_upgrade_authority() {
+    let mut market = Market::uninitialized();
+    let other = market.bank.wallet(SOL);
+    assert_eq!(
+        market.bank.process(&init_config_ix(&other, 0)),
+        Err(error(MarketplaceError::NotAdmin))
+    );
+    assert!(!market.bank.exists(&config_address()));
+}
+
+// anybody can send lamports to the config and treasury addresses before the setup , it must still go through
+#[test]
+fn init_config_with_prefunded_addresses() {
+    let mut market = Market::uninitialized();
+    let admin = market.admin;
+    let treasury_rent = rent(std::mem::size_of::<TreasuryData>());
+    market.bank.airdrop(&config_address(), 1);
+    market.bank.airdrop(&treasury_address(), treasury_rent + 7);
+
+    market.bank.process(&init_config_ix(&admin, 0)).unwrap();
+
+    let config_rent = rent(std::mem::size_of::<ConfigData>());
+    assert_eq!(market.bank.lamports(&config_address()), config_rent);
+    assert_eq!(market.bank.lamports(&treasury_address()), treasury_rent + 7);
+    assert_eq!(market.bank.lamports(&admin), 10 * SOL - config_rent + 1);
+    assert_eq!(market.config().admin, admin.to_bytes());
+    market.bank.load::<TreasuryData>(&treasury_address());
+}
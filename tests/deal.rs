@@ -0,0 +1,185 @@
+mod common;
+
+use common::*;
+use olx_contract::error::MarketplaceError;
+use olx_contract::state::{self, DealData, DealStatus, ImmutableRegistryData, InitAccountData, ListingStatus};
+use solana_program::pubkey::Pubkey;
+
+fn deal_rent() -> u64 {
+    rent(std::mem::size_of::<DealData>())
+}
+
+fn listing_status(market: &Market, listing: &Listing) -> ListingStatus {
+    market.bank.load::<InitAccountData>(&listing.key).status().unwrap()
+}
+
+fn market_with_listing(price: u64) -> (Market, Listing, Pubkey) {
+    let mut market = Market::new(0);
+    let seller = market.bank.wallet(SOL);
+    let buyer = market.bank.wallet(5 * SOL);
+    let listing = market.listing(&seller, price, None);
+    (market, listing, buyer)
+}
+
+// the deal account hold its rent reserve and the escrow , nothing else
+#[test]
+fn buy_puts_the_escrow_in_the_deal_account() {
+    let (mut market, listing, buyer) = market_with_listing(2 * SOL);
+    let deal = market.buy(&buyer, &listing);
+
+    assert_eq!(market.bank.lamports(&deal.key), deal_rent() + 2 * SOL);
+    assert_eq!(market.bank.lamports(&buyer), 5 * SOL - deal_rent() - 2 * SOL);
+    let data = market.deal(&deal);
+    assert_eq!((data.amount, data.price), (2 * SOL, 2 * SOL));
+    assert_eq!(data.listing, listing.key.to_bytes());
+    assert_eq!(data.rent_payer, buyer.to_bytes());
+    assert_eq!(listing_status(&market, &listing), ListingStatus::Reserved);
+
+    // a reserved listing can't be bought twice
+    let other = market.bank.wallet(5 * SOL);
+    let (buy, _) = market.buy_ix(&other, &listing);
+    assert_eq!(market.bank.process(&buy), Err(error(MarketplaceError::ListingNotActive)));
+}
+
+#[test]
+fn ship_only_before_the_accept_deadline() {
+    let (mut market, listing, buyer) = market_with_listing(SOL);
+    let deal = market.buy(&buyer, &listing);
+    market.bank.now = market.deal(&deal).accept_deadline;
+
+    assert_eq!(
+        market.bank.process(&ship_ix(&deal)),
+        Err(error(MarketplaceError::AcceptDeadlinePassed))
+    );
+}
+
+// nothing is released before SHIP
+#[test]
+fn sell_needs_a_shipped_deal() {
+    let (mut market, listing, buyer) = market_with_listing(SOL);
+    let deal = market.buy(&buyer, &listing);
+
+    assert!(market.bank.process(&settle_ix(&deal, false)).is_err());
+    assert_eq!(market.bank.lamports(&deal.key), deal_rent() + SOL);
+}
+
+#[test]
+fn claim_after_the_confirm_window() {
+    let (mut market, listing, buyer) = market_with_listing(SOL);
+    let deal = market.shipped(&buyer, &listing);
+    assert_eq!(
+        market.bank.process(&settle_ix(&deal, true)),
+        Err(error(MarketplaceError::ConfirmWindowOpen))
+    );
+
+    market.bank.now = market.deal(&deal).confirm_deadline;
+    let seller_before = market.bank.lamports(&deal.seller);
+    let buyer_before = market.bank.lamports(&buyer);
+    market.bank.process(&settle_ix(&deal, true)).unwrap();
+
+    // the seller settled , so under the default policy it paid the registry rent
+    let registry_rent = rent(std::mem::size_of::<ImmutableRegistryData>());
+    assert_eq!(market.bank.lamports(&deal.seller), seller_before + SOL - registry_rent);
+    assert_eq!(market.bank.lamports(&buyer), buyer_before + deal_rent());
+    assert!(!market.bank.exists(&deal.key));
+    assert_eq!(listing_status(&market, &listing), ListingStatus::Sold);
+}
+
+// lamports somebody sent to the deal are not escrow , they go to the rent payer with the reserve
+#[test]
+fn sell_gives_stray_lamports_to_the_rent_payer() {
+    let (mut market, listing, buyer) = market_with_listing(SOL);
+    let deal = market.shipped(&buyer, &listing);
+    market.bank.airdrop(&deal.key, 12_345);
+    let seller_before = market.bank.lamports(&deal.seller);
+    let buyer_before = market.bank.lamports(&buyer);
+
+    market.bank.process(&settle_ix(&deal, false)).unwrap();
+
+    let registry_rent = rent(std::mem::size_of::<ImmutableRegistryData>());
+    assert_eq!(market.bank.lamports(&deal.seller), seller_before + SOL);
+    assert_eq!(market.bank.lamports(&buyer), buyer_before + deal_rent() + 12_345 - registry_rent);
+}
+
+#[test]
+fn reject_refunds_a_shipped_deal() {
+    let (mut market, listing, buyer) = market_with_listing(SOL);
+    let deal = market.shipped(&buyer, &listing);
+
+    market.bank.process(&reject_ix(&deal)).unwrap();
+
+    assert_eq!(market.bank.lamports(&buyer), 5 * SOL);
+    assert!(!market.bank.exists(&deal.key));
+    assert_eq!(listing_status(&market, &listing), ListingStatus::Active);
+}
+
+#[test]
+fn cancel_only_before_ship() {
+    let (mut market, listing, buyer) = market_with_listing(SOL);
+    let deal = market.shipped(&buyer, &listing);
+
+    assert!(market.bank.process(&cancel_ix(&deal)).is_err());
+    assert_eq!(market.deal(&deal).status().unwrap(), DealStatus::Shipped);
+}
+
+// a closed deal is gone , the same CANCEL can't refund twice
+#[test]
+fn cancel_twice() {
+    let (mut market, listing, buyer) = market_with_listing(SOL);
+    let deal = market.buy(&buyer, &listing);
+    market.bank.process(&cancel_ix(&deal)).unwrap();
+
+    assert_eq!(
+        market.bank.process(&cancel_ix(&deal)),
+        Err(error(MarketplaceError::WrongAccountOwner))
+    );
+    assert_eq!(market.bank.lamports(&buyer), 5 * SOL);
+}
+
+#[test]
+fn expire_pays_a_crank_bounty() {
+    let (mut market, listing, buyer) = market_with_listing(2 * SOL);
+    let deal = market.buy(&buyer, &listing);
+    let crank = market.bank.wallet(SOL);
+    assert_eq!(
+        market.bank.process(&expire_ix(&deal, &crank)),
+        Err(error(MarketplaceError::AcceptWindowOpen))
+    );
+
+    market.bank.now = market.deal(&deal).accept_deadline;
+    market.bank.process(&expire_ix(&deal, &crank)).unwrap();
+
+    let bounty = state::expire_crank_bounty(2 * SOL);
+    assert!(bounty > 0);
+    assert_eq!(market.bank.lamports(&crank), SOL + bounty);
+    assert_eq!(market.bank.lamports(&buyer), 5 * SOL - bounty);
+    assert_eq!(listing_status(&market, &listing), ListingStatus::Active);
+}
+
+#[test]
+fn expire_by_the_buyer_pays_no_bounty() {
+    let (mut market, listing, buyer) = market_with_listing(2 * SOL);
+    let deal = market.buy(&buyer, &listing);
+    market.bank.now = market.deal(&deal).accept_deadline;
+
+    market.bank.process(&expire_ix(&deal, &buyer)).unwrap();
+    assert_eq!(market.bank.lamports(&buyer), 5 * SOL);
+}
+
+// a relayer funded the deal rent , the buyer only paid the escrow and the relayer get the rent back
+#[test]
+fn relayer_funded_deal() {
+    let (mut market, listing, buyer) = market_with_listing(SOL);
+    let relayer = market.bank.wallet(SOL);
+    let (buy, deal) = market.buy_ix(&buyer, &listing);
+    market.bank.process(&with_signer(buy, &relayer)).unwrap();
+    assert_eq!(market.bank.lamports(&buyer), 4 * SOL);
+    assert_eq!(market.bank.lamports(&relayer), SOL - deal_rent());
+
+    // the rent receiver must be passed , the buyer alone is refused
+    assert!(market.bank.process(&cancel_ix(&deal)).is_err());
+    market.bank.process(&with_account(cancel_ix(&deal), &relayer)).unwrap();
+
+    assert_eq!(market.bank.lamports(&buyer), 5 * SOL);
+    assert_eq!(market.bank.lamports(&relayer), SOL);
+}
@@ -0,0 +1,72 @@
+mod common;
+
+use common::*;
+use olx_contract::state::{DisputeRecordData, InitAccountData, ListingStatus};
+use solana_program::pubkey::Pubkey;
+
+// a market with an allowed arbiter and a disputed lamport deal of 2 SOL
+fn disputed() -> (Market, Pubkey, Deal) {
+    let mut market = Market::new(0);
+    let arbiter = market.bank.wallet(SOL);
+    market.set_config(|config| config.arbiters[0] = arbiter.to_bytes());
+    let seller = market.bank.wallet(SOL);
+    let buyer = market.bank.wallet(5 * SOL);
+    let listing = market.listing_with_arbiter(&seller, 2 * SOL, None, &arbiter);
+    let deal = market.shipped(&buyer, &listing);
+    market.bank.process(&open_dispute_ix(&deal, &buyer)).unwrap();
+    (market, arbiter, deal)
+}
+
+#[test]
+fn resolve_splits_the_escrow_and_writes_the_record() {
+    let (mut market, arbiter, deal) = disputed();
+    let deal_lamports = market.bank.lamports(&deal.key);
+    let seller_before = market.bank.lamports(&deal.seller);
+    let buyer_before = market.bank.lamports(&deal.buyer);
+
+    market.bank.process(&resolve_ix(&deal, &arbiter, 2_500)).unwrap();
+
+    let record_rent = rent(std::mem::size_of::<DisputeRecordData>());
+    assert_eq!(market.bank.lamports(&deal.seller), seller_before + SOL / 2);
+    assert_eq!(market.bank.lamports(&deal.buyer), buyer_before + deal_lamports - SOL / 2);
+    assert_eq!(market.bank.lamports(&arbiter), SOL - record_rent);
+    let record = market.bank.load::<DisputeRecordData>(&dispute_address(&deal.key));
+    assert_eq!((record.seller_amount, record.buyer_amount), (SOL / 2, 3 * SOL / 2));
+    assert_eq!(
+        market.bank.load::<InitAccountData>(&deal.listing.key).status().unwrap(),
+        ListingStatus::Sold
+    );
+}
+
+// lamports sent to the record address before the ruling count toward its rent instead of blocking it
+#[test]
+fn resolve_with_a_prefunded_record_address() {
+    let (mut market, arbiter, deal) = disputed();
+    let record = dispute_address(&deal.key);
+    market.bank.airdrop(&record, 1);
+
+    market.bank.process(&resolve_ix(&deal, &arbiter, 0)).unwrap();
+
+    let record_rent = rent(std::mem::size_of::<DisputeRecordData>());
+    assert_eq!(market.bank.lamports(&record), record_rent);
+    assert_eq!(market.bank.lamports(&arbiter), SOL - record_rent + 1);
+    assert_eq!(market.bank.load::<DisputeRecordData>(&record).buyer_amount, 2 * SOL);
+    assert_eq!(
+        market.bank.load::<InitAccountData>(&deal.listing.key).status().unwrap(),
+        ListingStatus::Active
+    );
+}
+
+// a deposit that already cover the rent leave the arbiter nothing to pay
+#[test]
+fn resolve_with_a_record_address_above_its_rent() {
+    let (mut market, arbiter, deal) = disputed();
+    let record = dispute_address(&deal.key);
+    let record_rent = rent(std::mem::size_of::<DisputeRecordData>());
+    market.bank.airdrop(&record, record_rent + 5);
+
+    market.bank.process(&resolve_ix(&deal, &arbiter, 10_000)).unwrap();
+
+    assert_eq!(market.bank.lamports(&record), record_rent + 5);
+    assert_eq!(market.bank.lamports(&arbiter), SOL);
+}
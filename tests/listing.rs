@@ -0,0 +1,108 @@
+mod common;
+
+use common::*;
+use olx_contract::error::MarketplaceError;
+use olx_contract::instruction::SetPauseData;
+use olx_contract::state::{DealStatus, ImmutableRegistryData, InitAccountData, ListingStatus};
+
+#[test]
+fn init_publish_buy_ship_sell() {
+    let mut market = Market::new(250);
+    let seller = market.bank.wallet(SOL);
+    let buyer = market.bank.wallet(5 * SOL);
+    let listing = market.listing(&seller, 2 * SOL, None);
+    assert_eq!(
+        market.bank.load::<InitAccountData>(&listing.key).status().unwrap(),
+        ListingStatus::Active
+    );
+
+    let deal = market.buy(&buyer, &listing);
+    let deal_rent = market.bank.lamports(&deal.key) - 2 * SOL;
+    assert_eq!(market.deal(&deal).amount, 2 * SOL);
+    assert_eq!(market.deal(&deal).status().unwrap(), DealStatus::Pending);
+    assert_eq!(
+        market.bank.load::<InitAccountData>(&listing.key).status().unwrap(),
+        ListingStatus::Reserved
+    );
+
+    market.bank.process(&ship_ix(&deal)).unwrap();
+    let seller_before = market.bank.lamports(&seller);
+    let buyer_before = market.bank.lamports(&buyer);
+    let treasury_before = market.bank.lamports(&treasury_address());
+    market.bank.process(&settle_ix(&deal, false)).unwrap();
+
+    // 2.5% of 2 SOL to the treasury , the rest to the seller , the deal rent back to the buyer who paid the registry rent
+    let registry = registry_address(&listing.item_id, &buyer, &seller);
+    let registry_rent = rent(std::mem::size_of::<ImmutableRegistryData>());
+    assert!(!market.bank.exists(&deal.key));
+    assert_eq!(market.bank.lamports(&treasury_address()), treasury_before + SOL / 20);
+    assert_eq!(market.bank.lamports(&seller), seller_before + 2 * SOL - SOL / 20);
+    assert_eq!(market.bank.lamports(&buyer), buyer_before + deal_rent - registry_rent);
+    assert_eq!(market.bank.load::<ImmutableRegistryData>(&registry).fee, SOL / 20);
+    assert_eq!(
+        market.bank.load::<InitAccountData>(&listing.key).status().unwrap(),
+        ListingStatus::Sold
+    );
+}
+
+#[test]
+fn cancel_gives_everything_back() {
+    let mut market = Market::new(250);
+    let seller = market.bank.wallet(SOL);
+    let buyer = market.bank.wallet(5 * SOL);
+    let listing = market.listing(&seller, 2 * SOL, None);
+    let deal = market.buy(&buyer, &listing);
+
+    market.bank.process(&cancel_ix(&deal)).unwrap();
+    assert_eq!(market.bank.lamports(&buyer), 5 * SOL);
+    assert!(!market.bank.exists(&deal.key));
+    assert_eq!(
+        market.bank.load::<InitAccountData>(&listing.key).status().unwrap(),
+        ListingStatus::Active
+    );
+}
+
+#[test]
+fn publish_refused_while_listings_paused() {
+    let mut market = Market::new(0);
+    let seller = market.bank.wallet(SOL);
+    let listing = Listing {
+        key: listing_address(&[7; 32], &seller),
+        seller,
+        seed: [7; 32],
+        item_id: [8; 32],
+        price: SOL,
+        mint: None,
+    };
+    market.bank.process(&init_ix(&seller, &listing, &Default::default())).unwrap();
+
+    let admin = market.admin;
+    let pause = |listings| SetPauseData {
+        listings,
+        buys: 0,
+        settlements: 0,
+    };
+    market.bank.process(&set_pause_ix(&admin, pause(1))).unwrap();
+    assert_eq!(
+        market.bank.process(&publish_ix(&seller, &listing)),
+        Err(error(MarketplaceError::MarketplacePaused))
+    );
+    market.bank.process(&set_pause_ix(&admin, pause(0))).unwrap();
+    market.bank.process(&publish_ix(&seller, &listing)).unwrap();
+}
+
+#[test]
+fn buy_refused_after_an_edit() {
+    let mut market = Market::new(0);
+    let seller = market.bank.wallet(SOL);
+    let buyer = market.bank.wallet(5 * SOL);
+    let listing = market.listing(&seller, SOL, None);
+    let (mut buy, _) = market.buy_ix(&buyer, &listing);
+    // the buyer saw revision 1 , the listing is still at 0
+    buy.data[2 + 96 + 8] = 1;
+    assert_eq!(
+        market.bank.process(&buy),
+        Err(error(MarketplaceError::ListingRevisionMismatch))
+    );
+    assert_eq!(market.bank.lamports(&buyer), 5 * SOL);
+}
@@ -0,0 +1,148 @@
+mod common;
+
+use common::*;
+use olx_contract::error::MarketplaceError;
+use olx_contract::state::{DealData, ImmutableRegistryData, RegistryRentPolicy};
+use solana_program::pubkey::Pubkey;
+
+fn registry_rent() -> u64 {
+    rent(std::mem::size_of::<ImmutableRegistryData>())
+}
+
+fn deal_rent() -> u64 {
+    rent(std::mem::size_of::<DealData>())
+}
+
+// a fee free market under policy with a seller , a buyer and a lamport listing of 1 SOL
+fn market(policy: RegistryRentPolicy) -> (Market, Pubkey, Pubkey) {
+    let mut market = Market::new(0);
+    market.set_config(|config| config.registry_rent_policy = policy as u8);
+    // the treasury has fees to sponsor with
+    market.bank.airdrop(&treasury_address(), SOL);
+    let seller = market.bank.wallet(SOL);
+    let buyer = market.bank.wallet(5 * SOL);
+    (market, seller, buyer)
+}
+
+// what the buyer , the seller and the treasury each paid of the registry rent on a lamport deal
+fn lamport_settlement(policy: RegistryRentPolicy, timeout_claim: bool) -> (u64, u64, u64) {
+    let (mut market, seller, buyer) = market(policy);
+    let listing = market.listing(&seller, SOL, None);
+    let deal = market.shipped(&buyer, &listing);
+    if timeout_claim {
+        market.bank.now = market.deal(&deal).confirm_deadline;
+    }
+    let before = [buyer, seller, treasury_address()].map(|key| market.bank.lamports(&key));
+
+    market.bank.process(&settle_ix(&deal, timeout_claim)).unwrap();
+
+    let registry = registry_address(&listing.item_id, &buyer, &seller);
+    assert_eq!(market.bank.lamports(&registry), registry_rent());
+    (
+        before[0] + deal_rent() - market.bank.lamports(&buyer),
+        before[1] + SOL - market.bank.lamports(&seller),
+        before[2] - market.bank.lamports(&treasury_address()),
+    )
+}
+
+#[test]
+fn lamport_deals_under_every_policy() {
+    use RegistryRentPolicy::*;
+    let rent = registry_rent();
+    assert_eq!(lamport_settlement(Settler, false), (rent, 0, 0));
+    assert_eq!(lamport_settlement(Settler, true), (0, rent, 0));
+    assert_eq!(lamport_settlement(Buyer, false), (rent, 0, 0));
+    // the buyer did not sign the CLAIM , its share come out of the seller payout
+    assert_eq!(lamport_settlement(Buyer, true), (0, rent, 0));
+    assert_eq!(lamport_settlement(Seller, false), (0, rent, 0));
+    assert_eq!(lamport_settlement(Split, false), (rent / 2, rent - rent / 2, 0));
+    assert_eq!(lamport_settlement(Treasury, false), (0, 0, rent));
+    assert_eq!(lamport_settlement(Proceeds, false), (0, rent, 0));
+}
+
+#[test]
+fn proceeds_too_small_for_the_rent() {
+    let (mut market, seller, buyer) = market(RegistryRentPolicy::Proceeds);
+    let listing = market.listing(&seller, registry_rent() - 1, None);
+    let deal = market.shipped(&buyer, &listing);
+
+    assert_eq!(
+        market.bank.process(&settle_ix(&deal, false)),
+        Err(error(MarketplaceError::RentAboveProceeds))
+    );
+}
+
+// a token listing with its mint , the seller and buyer token accounts ready
+fn token_listing(market: &mut Market, seller: &Pubkey, buyer: &Pubkey) -> Listing {
+    let mint = market.bank.mint(6);
+    market.bank.token_account(seller, &mint, 0);
+    market.bank.token_account(buyer, &mint, 1_000);
+    market.listing(seller, 400, Some(mint))
+}
+
+// the seller can't be charged on a SELL only the buyer sign , BUY refuse the deal up front
+#[test]
+fn token_buy_refused_when_the_seller_would_pay_on_sell() {
+    use RegistryRentPolicy::*;
+    for policy in [Seller, Split, Proceeds] {
+        let (mut market, seller, buyer) = market(policy);
+        let listing = token_listing(&mut market, &seller, &buyer);
+        let (buy, deal) = market.buy_ix(&buyer, &listing);
+
+        assert_eq!(
+            market.bank.process(&buy),
+            Err(error(MarketplaceError::RentPolicyNotForTokens))
+        );
+        assert!(!market.bank.exists(&deal.key));
+        assert_eq!(market.bank.token_balance(&ata(&buyer, &listing.mint.unwrap())), 1_000);
+    }
+}
+
+// under every policy BUY accept , the buyer settle a token deal alone
+#[test]
+fn token_sell_signed_by_the_buyer_alone() {
+    use RegistryRentPolicy::*;
+    for (policy, buyer_pays) in [(Settler, true), (Buyer, true), (Treasury, false)] {
+        let (mut market, seller, buyer) = market(policy);
+        let listing = token_listing(&mut market, &seller, &buyer);
+        let deal = market.shipped(&buyer, &listing);
+        let seller_before = market.bank.lamports(&seller);
+        let buyer_before = market.bank.lamports(&buyer);
+
+        market.bank.process(&settle_ix(&deal, false)).unwrap();
+
+        let rents = deal_rent() + rent(TOKEN_ACCOUNT_LEN);
+        let charged = if buyer_pays { registry_rent() } else { 0 };
+        assert_eq!(market.bank.lamports(&buyer), buyer_before + rents - charged);
+        assert_eq!(market.bank.lamports(&seller), seller_before);
+        assert_eq!(market.bank.token_balance(&ata(&seller, &listing.mint.unwrap())), 400);
+    }
+}
+
+// on CLAIM the seller sign , so the buyer share it owe is paid by the seller in lamports
+#[test]
+fn token_claim_under_the_buyer_policy() {
+    let (mut market, seller, buyer) = market(RegistryRentPolicy::Buyer);
+    let listing = token_listing(&mut market, &seller, &buyer);
+    let deal = market.shipped(&buyer, &listing);
+    market.bank.now = market.deal(&deal).confirm_deadline;
+    let seller_before = market.bank.lamports(&seller);
+
+    market.bank.process(&settle_ix(&deal, true)).unwrap();
+
+    assert_eq!(market.bank.lamports(&seller), seller_before - registry_rent());
+    assert_eq!(market.bank.token_balance(&ata(&seller, &listing.mint.unwrap())), 400);
+}
+
+// a policy changed after BUY don't reach the running deal
+#[test]
+fn policy_locked_at_buy() {
+    let (mut market, seller, buyer) = market(RegistryRentPolicy::Buyer);
+    let listing = token_listing(&mut market, &seller, &buyer);
+    let deal = market.shipped(&buyer, &listing);
+    market.set_config(|config| config.registry_rent_policy = RegistryRentPolicy::Seller as u8);
+    let seller_before = market.bank.lamports(&seller);
+
+    market.bank.process(&settle_ix(&deal, false)).unwrap();
+    assert_eq!(market.bank.lamports(&seller), seller_before);
+}
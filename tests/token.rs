@@ -0,0 +1,125 @@
+mod common;
+
+use common::*;
+use olx_contract::state::{DealData, ImmutableRegistryData};
+use solana_program::pubkey::Pubkey;
+
+// a market with a 5% fee , a 6 decimals mint and a buyer holding 1000 tokens of it
+fn token_market() -> (Market, Pubkey, Pubkey, Pubkey) {
+    let mut market = Market::new(500);
+    let mint = market.bank.mint(6);
+    let seller = market.bank.wallet(SOL);
+    let buyer = market.bank.wallet(5 * SOL);
+    market.bank.token_account(&seller, &mint, 0);
+    market.bank.token_account(&buyer, &mint, 1_000);
+    (market, mint, seller, buyer)
+}
+
+#[test]
+fn buy_moves_the_tokens_into_the_deal_vault() {
+    let (mut market, mint, seller, buyer) = token_market();
+    let listing = market.listing(&seller, 400, Some(mint));
+    let deal = market.buy(&buyer, &listing);
+
+    let vault = deal.vault().unwrap();
+    assert_eq!(market.bank.token_balance(&vault), 400);
+    assert_eq!(market.bank.token_balance(&ata(&buyer, &mint)), 600);
+    assert_eq!(market.deal(&deal).amount, 400);
+    assert_eq!(market.deal(&deal).token_account, vault.to_bytes());
+    // the buyer funded the deal and its vault , only the rent is in lamports
+    let rents = rent(std::mem::size_of::<DealData>()) + rent(TOKEN_ACCOUNT_LEN);
+    assert_eq!(market.bank.lamports(&buyer), 5 * SOL - rents);
+    assert_eq!(market.bank.lamports(&deal.key), rent(std::mem::size_of::<DealData>()));
+}
+
+#[test]
+fn sell_pays_the_seller_and_the_treasury_in_tokens() {
+    let (mut market, mint, seller, buyer) = token_market();
+    let listing = market.listing(&seller, 400, Some(mint));
+    let deal = market.shipped(&buyer, &listing);
+    let buyer_before = market.bank.lamports(&buyer);
+
+    market.bank.process(&settle_ix(&deal, false)).unwrap();
+
+    let treasury_ata = ata(&treasury_address(), &mint);
+    assert_eq!(market.bank.token_balance(&ata(&seller, &mint)), 380);
+    assert_eq!(market.bank.token_balance(&treasury_ata), 20);
+    assert!(!market.bank.exists(&deal.key));
+    assert!(!market.bank.exists(&deal.vault().unwrap()));
+    // the deal and vault rent come back , the buyer settled so it paid the registry and the treasury ATA
+    let registry_rent = rent(std::mem::size_of::<ImmutableRegistryData>());
+    let rents = rent(std::mem::size_of::<DealData>()) + rent(TOKEN_ACCOUNT_LEN);
+    assert_eq!(
+        market.bank.lamports(&buyer),
+        buyer_before + rents - registry_rent - rent(TOKEN_ACCOUNT_LEN)
+    );
+    let registry = registry_address(&listing.item_id, &buyer, &seller);
+    assert_eq!(market.bank.load::<ImmutableRegistryData>(&registry).fee, 20);
+}
+
+// tokens sent to the vault on top of the escrow go with the last share , the vault can close empty
+#[test]
+fn sell_pays_tokens_sent_to_the_vault_to_the_seller() {
+    let (mut market, mint, seller, buyer) = token_market();
+    let listing = market.listing(&seller, 400, Some(mint));
+    let deal = market.shipped(&buyer, &listing);
+    let vault = deal.vault().unwrap();
+    let mut account = market.bank.get(&vault);
+    account.data = token_account_data(&mint, &deal.key, 433);
+    market.bank.set(vault, account);
+
+    market.bank.process(&settle_ix(&deal, false)).unwrap();
+
+    assert_eq!(market.bank.token_balance(&ata(&seller, &mint)), 413);
+    assert_eq!(market.bank.token_balance(&ata(&treasury_address(), &mint)), 20);
+    assert!(!market.bank.exists(&vault));
+}
+
+#[test]
+fn cancel_returns_the_tokens_and_both_rents() {
+    let (mut market, mint, seller, buyer) = token_market();
+    let listing = market.listing(&seller, 400, Some(mint));
+    let deal = market.buy(&buyer, &listing);
+
+    market.bank.process(&cancel_ix(&deal)).unwrap();
+
+    assert_eq!(market.bank.token_balance(&ata(&buyer, &mint)), 1_000);
+    assert_eq!(market.bank.lamports(&buyer), 5 * SOL);
+    assert!(!market.bank.exists(&deal.key));
+    assert!(!market.bank.exists(&deal.vault().unwrap()));
+}
+
+#[test]
+fn resolve_splits_the_tokens() {
+    let (mut market, mint, seller, buyer) = token_market();
+    let arbiter = market.bank.wallet(SOL);
+    market.set_config(|config| config.arbiters[0] = arbiter.to_bytes());
+    let listing = market.listing_with_arbiter(&seller, 400, Some(mint), &arbiter);
+    let deal = market.shipped(&buyer, &listing);
+    market.bank.process(&open_dispute_ix(&deal, &seller)).unwrap();
+
+    market.bank.process(&resolve_ix(&deal, &arbiter, 7_500)).unwrap();
+
+    assert_eq!(market.bank.token_balance(&ata(&seller, &mint)), 300);
+    assert_eq!(market.bank.token_balance(&ata(&buyer, &mint)), 700);
+    assert_eq!(market.bank.lamports(&buyer), 5 * SOL);
+    assert!(!market.bank.exists(&deal.vault().unwrap()));
+}
+
+// the payout accounts are checked against the deal , a token account of somebody else is refused
+#[test]
+fn sell_refuses_a_foreign_seller_token_account() {
+    let (mut market, mint, seller, buyer) = token_market();
+    let listing = market.listing(&seller, 400, Some(mint));
+    let deal = market.shipped(&buyer, &listing);
+    let mut sell = settle_ix(&deal, false);
+    let thief = market.bank.wallet(SOL);
+    let thief_ata = market.bank.token_account(&thief, &mint, 0);
+    sell.accounts[11].pubkey = thief_ata;
+
+    assert_eq!(
+        market.bank.process(&sell),
+        Err(error(olx_contract::error::MarketplaceError::TokenAccountMismatch))
+    );
+    assert_eq!(market.bank.token_balance(&deal.vault().unwrap()), 400);
+}